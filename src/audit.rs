@@ -0,0 +1,202 @@
+//! Verbatim copies of everything sent to children
+//!
+//! When a child misbehaves, rerunning the whole pipeline to reproduce its input can be slow or
+//! impossible. An audit writes the exact bytes of every record sent to a child to its own file,
+//! named so the records can be replayed in order, and to the same workers. How the records were
+//! sent, e.g. the payloads written around them, is saved alongside them so replays match.
+use super::pool::Error;
+#[cfg(unix)]
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, ErrorKind};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// How each record was passed to its child
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Delivery {
+    /// Written to the stdin of the child
+    #[default]
+    Stdin,
+    /// In the environment variable with this name, with a new child for every record
+    #[cfg(unix)]
+    Env(OsString),
+    /// In a file the child reads, with a new child for every record
+    File,
+}
+
+/// How the records of an audit were sent to children, which a replay needs to reproduce
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Setup {
+    /// What was written to the stdin of every child before any records
+    pub init: Vec<u8>,
+    /// What was written to the stdin of every child after all of its records
+    pub finish: Vec<u8>,
+    /// How each record was passed to its child
+    pub delivery: Delivery,
+}
+
+/// A file in an audit directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Task {
+    /// The sequence number of the record
+    pub seq: u64,
+    /// The worker the record was sent to
+    pub worker: usize,
+    /// The file with a copy of the record sent
+    pub path: PathBuf,
+}
+
+impl Task {
+    /// Parse the name of a file in an audit directory, `<seq>-<worker>`
+    #[must_use]
+    pub fn parse(dir: &Path, name: &str) -> Option<Self> {
+        let (seq, worker) = name.split_once('-')?;
+        Some(Task {
+            seq: seq.parse().ok()?,
+            worker: worker.parse().ok()?,
+            path: dir.join(name),
+        })
+    }
+}
+
+/// A writer of a copy of every record sent to a process
+///
+/// Each record is written to a file in a directory named by its sequence number, zero padded so
+/// the files sort in order, and the worker it was sent to. Workers are numbered by the slot of
+/// the pool they ran in. The setup is saved in the files `init`, `final`, and `delivery`.
+#[derive(Debug)]
+pub struct Audit {
+    dir: PathBuf,
+    seq: u64,
+}
+
+impl Audit {
+    /// Create a new audit of records sent with `setup` that writes to `dir`, creating it if it
+    /// doesn't exist
+    ///
+    /// # Errors
+    ///
+    /// If the directory or setup can't be written.
+    pub fn create(dir: impl Into<PathBuf>, setup: &Setup) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("init"), &setup.init)?;
+        fs::write(dir.join("final"), &setup.finish)?;
+        let delivery: &[u8] = match &setup.delivery {
+            Delivery::Stdin => b"stdin",
+            #[cfg(unix)]
+            Delivery::Env(name) => &[b"env ", name.as_bytes()].concat(),
+            Delivery::File => b"file",
+        };
+        fs::write(dir.join("delivery"), delivery)?;
+        Ok(Audit { dir, seq: 0 })
+    }
+
+    /// Write a copy of `sent`, the bytes of a single record sent to `worker`
+    ///
+    /// # Errors
+    ///
+    /// If the file can't be written.
+    pub fn record(&mut self, sent: &[u8], worker: usize) -> Result<(), Error> {
+        let path = self.dir.join(format!("{:08}-{worker}", self.seq));
+        fs::write(path, sent).map_err(Error::Audit)?;
+        self.seq += 1;
+        Ok(())
+    }
+}
+
+/// Read every task in an audit directory written by a previous run, in order
+///
+/// Files that weren't written by an audit are ignored.
+///
+/// # Errors
+///
+/// If there are problems reading the directory.
+pub fn read_audit(dir: &Path) -> io::Result<Vec<Task>> {
+    let mut tasks = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        if let Some(task) = name.to_str().and_then(|name| Task::parse(dir, name)) {
+            tasks.push(task);
+        }
+    }
+    tasks.sort_by_key(|task| task.seq);
+    Ok(tasks)
+}
+
+/// Read how the records in an audit directory written by a previous run were sent
+///
+/// # Errors
+///
+/// If there are problems reading the directory, or the setup isn't valid.
+pub fn read_setup(dir: &Path) -> io::Result<Setup> {
+    let delivery = fs::read(dir.join("delivery"))?;
+    let delivery = match &delivery[..] {
+        b"stdin" => Delivery::Stdin,
+        b"file" => Delivery::File,
+        #[cfg(unix)]
+        [b'e', b'n', b'v', b' ', name @ ..] => Delivery::Env(OsStr::from_bytes(name).to_owned()),
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid audit delivery",
+            ))
+        }
+    };
+    Ok(Setup {
+        init: fs::read(dir.join("init"))?,
+        finish: fs::read(dir.join("final"))?,
+        delivery,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_audit, read_setup, Audit, Delivery, Setup, Task};
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process;
+
+    #[test]
+    fn round_trip() {
+        let dir = env::temp_dir().join(format!("xstream-audit-{}", process::id()));
+        let setup = Setup {
+            init: b"begin\n".to_vec(),
+            finish: Vec::new(),
+            #[cfg(unix)]
+            delivery: Delivery::Env("RECORD".into()),
+            #[cfg(not(unix))]
+            delivery: Delivery::File,
+        };
+        let mut audit = Audit::create(&dir, &setup).unwrap();
+        audit.record(b"a\n", 1).unwrap();
+        audit.record(b"b\n", 0).unwrap();
+        let tasks = read_audit(&dir).unwrap();
+        let setup_read = read_setup(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(setup_read, setup);
+        assert_eq!(
+            tasks.iter().map(|task| task.worker).collect::<Vec<_>>(),
+            [1, 0]
+        );
+    }
+
+    #[test]
+    fn parse_task() {
+        let dir = Path::new("audit");
+        assert_eq!(
+            Task::parse(dir, "00000012-3"),
+            Some(Task {
+                seq: 12,
+                worker: 3,
+                path: dir.join("00000012-3"),
+            })
+        );
+        assert_eq!(Task::parse(dir, "notes.txt"), None);
+        assert_eq!(Task::parse(dir, "1-2-3"), None);
+    }
+}
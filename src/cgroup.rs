@@ -0,0 +1,115 @@
+//! cgroup v2 resource limits
+//!
+//! A cgroup bounds the resources of every process inside of it as a unit. Attaching a `Command`
+//! to a cgroup means every child spawned from it starts inside the cgroup, so the limits apply to
+//! all workers of a pool together, instead of to each process individually.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+/// The default period used for `cpu.max` in microseconds
+const CPU_PERIOD: u32 = 100_000;
+
+/// A dedicated cgroup that spawned children can be placed into
+///
+/// The cgroup directory is created on construction and removed when this is dropped, so this
+/// should outlive any pool that spawns into it.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    procs: Arc<File>,
+}
+
+impl Cgroup {
+    /// Create a new cgroup at `path`
+    ///
+    /// `path` should be a new directory inside a mounted cgroup v2 hierarchy that the current
+    /// user is allowed to manage, e.g. `/sys/fs/cgroup/xstream`.
+    ///
+    /// # Errors
+    ///
+    /// If the directory can't be created or isn't a cgroup.
+    pub fn create(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        fs::create_dir(&path)?;
+        let procs = OpenOptions::new()
+            .write(true)
+            .open(path.join("cgroup.procs"))
+            .inspect_err(|_| {
+                let _ = fs::remove_dir(&path);
+            })?;
+        Ok(Cgroup {
+            path,
+            procs: Arc::new(procs),
+        })
+    }
+
+    /// The path to the cgroup directory
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Limit the total memory of the cgroup to `bytes`
+    ///
+    /// # Errors
+    ///
+    /// If the memory controller isn't enabled in the parent's `cgroup.subtree_control`, or the limit
+    /// can't be written.
+    pub fn set_memory_max(&self, bytes: u64) -> io::Result<()> {
+        self.write_control("memory", "memory.max", &bytes.to_string())
+    }
+
+    /// Limit the total cpu usage of the cgroup to `cpus` cpus worth of time
+    ///
+    /// Fractional values are allowed, e.g. `0.5` allows half of one cpu.
+    ///
+    /// # Errors
+    ///
+    /// If the cpu controller isn't enabled in the parent's `cgroup.subtree_control`, or the limit
+    /// can't be written.
+    pub fn set_cpu_max(&self, cpus: f64) -> io::Result<()> {
+        // NOTE the kernel rejects quotas below 1ms
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let quota = ((cpus * f64::from(CPU_PERIOD)) as u64).max(1_000);
+        self.write_control("cpu", "cpu.max", &format!("{quota} {CPU_PERIOD}"))
+    }
+
+    /// Place every child spawned from `command` into this cgroup
+    ///
+    /// Children move themselves into the cgroup before they exec, so any processes they spawn
+    /// are also constrained.
+    pub fn attach(&self, command: &mut Command) {
+        let procs = Arc::clone(&self.procs);
+        // SAFETY: writing to an already open file is async signal safe and doesn't allocate
+        unsafe {
+            command.pre_exec(move || (&*procs).write_all(b"0"));
+        }
+    }
+
+    /// Write `value` to a controller file
+    ///
+    /// The parent's `cgroup.subtree_control` is shared with any sibling cgroups, so `controller`
+    /// isn't enabled here, and it's an error if it isn't already.
+    fn write_control(&self, controller: &str, file: &str, value: &str) -> io::Result<()> {
+        let control = self.path.join(file);
+        if !control.exists() {
+            let parent = self.path.parent().unwrap_or(&self.path);
+            return Err(io::Error::other(format!(
+                "the {controller} controller isn't enabled in {}",
+                parent.join("cgroup.subtree_control").display()
+            )));
+        }
+        fs::write(control, value)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // this only succeeds once every process in the cgroup has exited
+        let _ = fs::remove_dir(&self.path);
+    }
+}
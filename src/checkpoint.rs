@@ -0,0 +1,151 @@
+//! Dispatch state that can be saved and restored
+//!
+//! A long running xstream, e.g. one serving a socket, loses track of where it was if it's
+//! restarted. With the `serde` feature a checkpoint can be persisted in any format, and restoring
+//! it continues routing where it left off, so keys keep going to the same worker.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How a pool chooses its next process
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Routing {
+    /// A `Rotating` pool
+    Rotating {
+        /// The maximum number of processes, or zero if unbounded
+        procs: usize,
+        /// The index of the next process in round-robin order
+        next: usize,
+    },
+    /// A `Random` pool
+    Random {
+        /// The maximum number of processes, or zero if unbounded
+        procs: usize,
+        /// The state of the random number generator
+        ///
+        /// This is serialized as a hex string, since formats like TOML can't hold every `u64`.
+        #[cfg_attr(feature = "serde", serde(with = "hex_u64"))]
+        state: u64,
+    },
+    /// A `Partitioned` pool
+    ///
+    /// Keys are hashed to partitions with a stable hash, so they're assigned to the same
+    /// partitions as long as the number of processes doesn't change.
+    Partitioned {
+        /// The number of partitions
+        procs: usize,
+        /// The index of the next process for records without a key
+        next: usize,
+    },
+    /// A `Routed` pool
+    Routed {
+        /// The number of processes
+        procs: usize,
+        /// The index of the next process for records the route can't be evaluated for
+        next: usize,
+        /// The index of the next process `rr()` evaluates to in the route
+        rr: usize,
+    },
+}
+
+/// Serialize a `u64` as a hex string
+#[cfg(feature = "serde")]
+mod hex_u64 {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    // serde's `with` passes fields by reference
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn serialize<S: Serializer>(val: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{val:016x}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        u64::from_str_radix(&hex, 16).map_err(D::Error::custom)
+    }
+}
+
+/// A snapshot of how far input has been split and where it went
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Checkpoint {
+    /// The offset in the input of the next record
+    pub offset: u64,
+    /// The number of records sent to each worker
+    ///
+    /// Workers are numbered like the pool numbers its processes, by spawn order or partition.
+    pub records: Vec<u64>,
+    /// The routing state of the pool, if it has any
+    pub routing: Option<Routing>,
+}
+
+impl Checkpoint {
+    /// Create a new checkpoint for the start of the input
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a record of `length` bytes of input, including its delimiter, sent to `worker`
+    pub fn record(&mut self, length: u64, worker: usize) {
+        self.offset += length;
+        if self.records.len() <= worker {
+            self.records.resize(worker + 1, 0);
+        }
+        self.records[worker] += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Checkpoint;
+
+    #[test]
+    fn records() {
+        let mut checkpoint = Checkpoint::new();
+        checkpoint.record(4, 2);
+        checkpoint.record(3, 0);
+        checkpoint.record(5, 2);
+        assert_eq!(checkpoint.offset, 12);
+        assert_eq!(checkpoint.records, [1, 0, 2]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trip() {
+        use super::Routing;
+
+        let checkpoint = Checkpoint {
+            offset: 12,
+            records: vec![1, 0, 2],
+            routing: Some(Routing::Partitioned { procs: 3, next: 1 }),
+        };
+        let text = toml::to_string(&checkpoint).unwrap();
+        assert_eq!(
+            text,
+            "offset = 12\nrecords = [1, 0, 2]\n\n[routing.partitioned]\nprocs = 3\nnext = 1\n"
+        );
+        assert_eq!(toml::from_str::<Checkpoint>(&text).unwrap(), checkpoint);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trip_random() {
+        use super::Routing;
+
+        let checkpoint = Checkpoint {
+            offset: 0,
+            records: Vec::new(),
+            routing: Some(Routing::Random {
+                procs: 2,
+                state: u64::MAX - 1,
+            }),
+        };
+        let text = toml::to_string(&checkpoint).unwrap();
+        assert!(text.contains("state = \"fffffffffffffffe\""));
+        assert_eq!(toml::from_str::<Checkpoint>(&text).unwrap(), checkpoint);
+    }
+}
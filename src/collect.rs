@@ -0,0 +1,93 @@
+//! Collected stdout for child processes
+//!
+//! Output from children is usually passed straight through, so there's no way to tell where the
+//! output of one child ends and the next begins, and results that contain newlines can't be
+//! split apart again downstream. This captures the stdout of every child, and once the child
+//! exits, writes everything it wrote to the stdout of this process as one terminated result.
+use super::pool::{Error, Spawn};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::panic;
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// A spawner that writes the stdout of every child as one result followed by a terminator
+///
+/// Without reusing processes every record gets its own child, so every record gets its own
+/// result.
+#[derive(Debug)]
+pub struct Collected<S> {
+    inner: S,
+    terminator: u8,
+    readers: HashMap<u32, JoinHandle<io::Result<Vec<u8>>>>,
+}
+
+impl<S: Spawn> Collected<S> {
+    /// Wrap `inner` so that the stdout of each child it spawns is followed by `terminator`
+    ///
+    /// Using null lets results that contain newlines be passed intact to tools like `xargs -0` or
+    /// `sort -z`. Any stdout configured on the command of `inner` will be replaced.
+    pub fn new(mut inner: S, terminator: u8) -> Self {
+        inner.command().stdout(Stdio::piped());
+        Collected {
+            inner,
+            terminator,
+            readers: HashMap::new(),
+        }
+    }
+}
+
+impl<S: Spawn> Spawn for Collected<S> {
+    fn command(&mut self) -> &mut Command {
+        self.inner.command()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        let mut child = self.inner.spawn(slot)?;
+        if let Some(mut stdout) = child.stdout.take() {
+            // NOTE output is read as it's written so children don't block on a full pipe
+            self.readers.insert(
+                child.id(),
+                thread::spawn(move || {
+                    let mut result = Vec::new();
+                    stdout.read_to_end(&mut result)?;
+                    Ok(result)
+                }),
+            );
+        }
+        Ok(child)
+    }
+
+    fn wait(&mut self, child: Child) -> Result<(), Error> {
+        let reader = self.readers.remove(&child.id());
+        let res = self.inner.wait(child);
+        if let Some(reader) = reader {
+            let result = reader
+                .join()
+                .unwrap_or_else(|err| panic::resume_unwind(err))
+                .map_err(Error::Relay)?;
+            emit(&result, self.terminator, io::stdout().lock()).map_err(Error::Relay)?;
+        }
+        res
+    }
+}
+
+/// Write `result` to `out` followed by `terminator`
+fn emit(result: &[u8], terminator: u8, mut out: impl Write) -> io::Result<()> {
+    out.write_all(result)?;
+    out.write_all(&[terminator])?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::emit;
+
+    #[test]
+    fn keeps_newlines() {
+        let mut out = Vec::new();
+        emit(b"a\nb\n", b'\0', &mut out).unwrap();
+        emit(b"", b'\0', &mut out).unwrap();
+        assert_eq!(out, b"a\nb\n\0\0");
+    }
+}
@@ -0,0 +1,70 @@
+//! Decoding of armored records
+//!
+//! Binary payloads are often armored as text so they can be delimited by newlines. Decoding them
+//! while splitting avoids spawning a separate process per record just to decode it.
+use super::pool::Error;
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+
+/// A base64 engine that accepts input with or without padding
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// How each record is encoded
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoding {
+    /// Standard base64, with or without padding
+    Base64,
+    /// Hexadecimal, with either upper or lower case digits
+    Hex,
+}
+
+impl Decoding {
+    /// Decode `record` and append the result to `buf`
+    ///
+    /// # Errors
+    ///
+    /// If `record` isn't valid for this encoding.
+    pub fn decode(self, record: &[u8], buf: &mut Vec<u8>) -> Result<(), Error> {
+        match self {
+            Decoding::Base64 => BASE64.decode_vec(record, buf).map_err(|_| Error::Decode),
+            Decoding::Hex => {
+                let start = buf.len();
+                buf.resize(start + record.len() / 2, 0);
+                hex::decode_to_slice(record, &mut buf[start..]).map_err(|_| {
+                    buf.truncate(start);
+                    Error::Decode
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decoding;
+
+    fn decode(decoding: Decoding, record: &[u8]) -> Option<Vec<u8>> {
+        let mut buf = b"prefix".to_vec();
+        decoding.decode(record, &mut buf).ok()?;
+        Some(buf.split_off(6))
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(decode(Decoding::Base64, b"aGk="), Some(b"hi".to_vec()));
+        assert_eq!(decode(Decoding::Base64, b"aGk"), Some(b"hi".to_vec()));
+        assert_eq!(decode(Decoding::Base64, b"a*k="), None);
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(decode(Decoding::Hex, b"00fFa0"), Some(vec![0, 255, 160]));
+        assert_eq!(decode(Decoding::Hex, b"abc"), None);
+        assert_eq!(decode(Decoding::Hex, b"zz"), None);
+    }
+}
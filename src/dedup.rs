@@ -0,0 +1,76 @@
+//! Duplicate record detection
+//!
+//! Streams often contain runs of identical records. Dropping them before they're dispatched
+//! avoids doing the same work, or spawning a process, for each repeat.
+use std::collections::VecDeque;
+
+/// Detects records that were recently seen
+///
+/// This remembers a small window of the most recently seen distinct records. A window of one only
+/// detects consecutive duplicates, like `uniq`.
+#[derive(Debug, Clone)]
+pub struct Dedup {
+    recent: VecDeque<Vec<u8>>,
+    window: usize,
+}
+
+impl Dedup {
+    /// Create a new detector that remembers up to `window` distinct records
+    ///
+    /// A `window` of zero is treated as one.
+    #[must_use]
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Dedup {
+            recent: VecDeque::with_capacity(window),
+            window,
+        }
+    }
+
+    /// Check if `record` is a duplicate of a recently seen record
+    ///
+    /// Either way, `record` becomes the most recently seen record.
+    pub fn is_duplicate(&mut self, record: &[u8]) -> bool {
+        let found = self.recent.iter().position(|prev| prev == record);
+        // reuse the found record, or evict the least recently seen one if the window is full
+        let evicted = match found {
+            Some(pos) => self.recent.remove(pos),
+            None if self.recent.len() == self.window => self.recent.pop_back(),
+            None => None,
+        };
+        let mut prev = evicted.unwrap_or_default();
+        if found.is_none() {
+            prev.clear();
+            prev.extend_from_slice(record);
+        }
+        self.recent.push_front(prev);
+        found.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dedup;
+
+    fn kept(window: usize, records: &[&str]) -> Vec<String> {
+        let mut dedup = Dedup::new(window);
+        records
+            .iter()
+            .filter(|rec| !dedup.is_duplicate(rec.as_bytes()))
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn consecutive() {
+        assert_eq!(kept(1, &["a", "a", "b", "a", "a"]), ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn window() {
+        assert_eq!(
+            kept(2, &["a", "b", "a", "c", "b", "a"]),
+            ["a", "b", "c", "b", "a"]
+        );
+    }
+}
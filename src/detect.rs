@@ -0,0 +1,53 @@
+//! Guessing the delimiter of unknown input
+//!
+//! Control characters like null and the ascii record separator almost never appear in text, so if
+//! they're present they're almost certainly the delimiter. Otherwise the input is most likely
+//! lines, and tabs are only used if there aren't any.
+
+/// Guess the delimiter of input that starts with `sample`
+///
+/// Chooses among null, the ascii record separator (0x1E), `\r\n`, `\n`, and tab, by how often they
+/// appear in `sample`. Returns `None` if none of them appear.
+#[must_use]
+// the sample is only a single buffer, so counting doesn't need to be fast
+#[allow(clippy::naive_bytecount)]
+pub fn detect_delimiter(sample: &[u8]) -> Option<&'static [u8]> {
+    let count = |byte| sample.iter().filter(|&&b| b == byte).count();
+    let nulls = count(b'\0');
+    let separators = count(b'\x1e');
+    let newlines = count(b'\n');
+    if nulls > 0 || separators > 0 {
+        Some(if nulls >= separators { b"\0" } else { b"\x1e" })
+    } else if newlines > 0 {
+        let crlfs = sample.windows(2).filter(|w| w == b"\r\n").count();
+        Some(if crlfs == newlines { b"\r\n" } else { b"\n" })
+    } else if count(b'\t') > 0 {
+        Some(b"\t")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::detect_delimiter;
+
+    #[test]
+    fn lines() {
+        assert_eq!(detect_delimiter(b"a\tb\nc\td\n"), Some(&b"\n"[..]));
+        assert_eq!(detect_delimiter(b"a\r\nb\r\nc"), Some(&b"\r\n"[..]));
+        assert_eq!(detect_delimiter(b"a\r\nb\nc"), Some(&b"\n"[..]));
+    }
+
+    #[test]
+    fn control_characters() {
+        assert_eq!(detect_delimiter(b"a\nb\0c\n\0"), Some(&b"\0"[..]));
+        assert_eq!(detect_delimiter(b"a\nb\x1ec\x1e"), Some(&b"\x1e"[..]));
+    }
+
+    #[test]
+    fn tabs() {
+        assert_eq!(detect_delimiter(b"a\tb\tc"), Some(&b"\t"[..]));
+        assert_eq!(detect_delimiter(b"abc"), None);
+    }
+}
@@ -0,0 +1,222 @@
+//! Length prefixed framing of records
+//!
+//! Binary streams may not have any byte sequence that's safe to use as a delimiter. Instead each
+//! record can be prefixed with its length, so records are read without scanning for a delimiter.
+//! Records can also be written to children with the same framing, so they don't have to scan for
+//! one either.
+use super::pool::Error;
+use std::io::{BufRead, Read, Write};
+
+/// The longest length prefix of a netstring, enough for any `u64`
+const MAX_NETSTRING_DIGITS: usize = 20;
+
+/// The longest varint length prefix, enough for any `u64`
+const MAX_VARINT_BYTES: usize = 10;
+
+/// How the length of each record is encoded
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Netstrings, the decimal length followed by `:`, the record, and then `,`
+    Netstring,
+    /// The length as a little endian 32 bit integer followed by the record
+    U32Le,
+    /// The length as a protobuf varint followed by the record, as written by `writeDelimitedTo`
+    Varint,
+}
+
+impl Framing {
+    /// Read a single record from `in_handle` and append it to `buf`
+    ///
+    /// The framing isn't included in `buf`. Returns false if the input ended before the record.
+    ///
+    /// # Errors
+    ///
+    /// If there are problems reading from `in_handle`, or the input isn't correctly framed.
+    pub fn read_frame(
+        self,
+        in_handle: &mut impl BufRead,
+        buf: &mut Vec<u8>,
+    ) -> Result<bool, Error> {
+        if in_handle.fill_buf().map_err(Error::Input)?.is_empty() {
+            return Ok(false);
+        }
+        let len = match self {
+            Framing::Netstring => {
+                let mut prefix = Vec::with_capacity(MAX_NETSTRING_DIGITS + 1);
+                in_handle
+                    .take(MAX_NETSTRING_DIGITS as u64 + 1)
+                    .read_until(b':', &mut prefix)
+                    .map_err(Error::Input)?;
+                prefix
+                    .strip_suffix(b":")
+                    .and_then(|digits| std::str::from_utf8(digits).ok())
+                    .and_then(|digits| digits.parse().ok())
+                    .ok_or(Error::Frame)?
+            }
+            Framing::U32Le => {
+                let mut prefix = [0; 4];
+                read_exact(in_handle, &mut prefix)?;
+                u64::from(u32::from_le_bytes(prefix))
+            }
+            Framing::Varint => read_varint(in_handle)?,
+        };
+        let read = in_handle.take(len).read_to_end(buf).map_err(Error::Input)?;
+        if read as u64 != len {
+            return Err(Error::Frame);
+        }
+        if self == Framing::Netstring {
+            let mut suffix = [0];
+            read_exact(in_handle, &mut suffix)?;
+            if suffix != *b"," {
+                return Err(Error::Frame);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Write `record` to `out` with this framing
+    ///
+    /// # Errors
+    ///
+    /// If there are problems writing to `out`, or `record` is too long for this framing.
+    pub fn write_frame(self, out: &mut impl Write, record: &[u8]) -> Result<(), Error> {
+        match self {
+            Framing::Netstring => {
+                write!(out, "{}:", record.len()).map_err(Error::Output)?;
+                out.write_all(record).map_err(Error::Output)?;
+                out.write_all(b",").map_err(Error::Output)
+            }
+            Framing::U32Le => {
+                let len = u32::try_from(record.len()).map_err(|_| Error::Frame)?;
+                out.write_all(&len.to_le_bytes()).map_err(Error::Output)?;
+                out.write_all(record).map_err(Error::Output)
+            }
+            Framing::Varint => {
+                let mut prefix = [0; MAX_VARINT_BYTES];
+                let mut len = record.len() as u64;
+                let mut size = 0;
+                while {
+                    // the low 7 bits, with the high bit set if there are more
+                    prefix[size] = (len & 0x7f) as u8 | if len > 0x7f { 0x80 } else { 0 };
+                    len >>= 7;
+                    size += 1;
+                    len > 0
+                } {}
+                out.write_all(&prefix[..size]).map_err(Error::Output)?;
+                out.write_all(record).map_err(Error::Output)
+            }
+        }
+    }
+}
+
+/// Read a protobuf varint from `in_handle`
+fn read_varint(in_handle: &mut impl BufRead) -> Result<u64, Error> {
+    let mut val = 0;
+    for ind in 0..MAX_VARINT_BYTES {
+        let mut byte = [0];
+        read_exact(in_handle, &mut byte)?;
+        let bits = u64::from(byte[0] & 0x7f);
+        // the last byte can only have one bit left for a u64
+        if ind == MAX_VARINT_BYTES - 1 && bits > 1 {
+            return Err(Error::Frame);
+        }
+        val |= bits << (7 * ind);
+        if byte[0] & 0x80 == 0 {
+            return Ok(val);
+        }
+    }
+    Err(Error::Frame)
+}
+
+/// Fill `buf` from `in_handle`, where ending early is a framing error
+fn read_exact(in_handle: &mut impl BufRead, buf: &mut [u8]) -> Result<(), Error> {
+    in_handle.read_exact(buf).map_err(|err| {
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            Error::Frame
+        } else {
+            Error::Input(err)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Framing;
+
+    fn read_all(framing: Framing, mut input: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let mut records = Vec::new();
+        let mut buf = Vec::new();
+        while framing.read_frame(&mut input, &mut buf).ok()? {
+            records.push(buf.split_off(0));
+        }
+        Some(records)
+    }
+
+    #[test]
+    fn netstring() {
+        let records = read_all(Framing::Netstring, b"5:hello,0:,3:a\nb,");
+        assert_eq!(
+            records,
+            Some(vec![b"hello".to_vec(), Vec::new(), b"a\nb".to_vec()])
+        );
+    }
+
+    #[test]
+    fn invalid_netstring() {
+        assert_eq!(read_all(Framing::Netstring, b"5:hello;"), None);
+        assert_eq!(read_all(Framing::Netstring, b"x:hello,"), None);
+        assert_eq!(read_all(Framing::Netstring, b"5:hel"), None);
+        assert_eq!(
+            read_all(Framing::Netstring, b"123456789012345678901:"),
+            None
+        );
+    }
+
+    #[test]
+    fn u32le() {
+        let records = read_all(Framing::U32Le, b"\x02\0\0\0hi\0\0\0\0\x01\0\0\0\0");
+        assert_eq!(
+            records,
+            Some(vec![b"hi".to_vec(), Vec::new(), b"\0".to_vec()])
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let long = vec![7; 300];
+        for framing in [Framing::Netstring, Framing::U32Le, Framing::Varint] {
+            let mut out = Vec::new();
+            framing.write_frame(&mut out, b"hello").unwrap();
+            framing.write_frame(&mut out, b"").unwrap();
+            framing.write_frame(&mut out, &long).unwrap();
+            let records = read_all(framing, &out);
+            assert_eq!(
+                records,
+                Some(vec![b"hello".to_vec(), Vec::new(), long.clone()])
+            );
+        }
+    }
+
+    #[test]
+    fn varint() {
+        let mut input = vec![0xac, 0x02];
+        input.extend([b'x'; 300]);
+        input.push(0);
+        let records = read_all(Framing::Varint, &input);
+        assert_eq!(records, Some(vec![vec![b'x'; 300], Vec::new()]));
+    }
+
+    #[test]
+    fn invalid_varint() {
+        assert_eq!(read_all(Framing::Varint, b"\x80"), None);
+        assert_eq!(read_all(Framing::Varint, &[0xff; 11]), None);
+        assert_eq!(read_all(Framing::Varint, b"\x02x"), None);
+    }
+
+    #[test]
+    fn truncated_u32le() {
+        assert_eq!(read_all(Framing::U32Le, b"\x02\0"), None);
+        assert_eq!(read_all(Framing::U32Le, b"\x02\0\0\0h"), None);
+    }
+}
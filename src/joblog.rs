@@ -0,0 +1,122 @@
+//! Resource usage accounting for child processes
+//!
+//! Some records are much more expensive to process than others, and it's hard to tell which after
+//! the fact. This reaps children with `wait4` to get the resources each one used, and writes them
+//! to a log as children exit.
+use super::pool::{Error, Spawn};
+use std::borrow::BorrowMut;
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Write};
+use std::mem::MaybeUninit;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, ExitStatus};
+use std::time::{Duration, Instant};
+
+/// The columns of the log
+const HEADER: &str = "pid\texit\tsignal\tseconds\tuser\tsystem\tmax_rss_kib\n";
+
+/// A spawner that logs the exit status and resource usage of every child
+///
+/// The log is tab separated with a header line, and a line is written for every child when it's
+/// waited on. Times are in seconds, and the maximum resident set size is in kibibytes. Wall time
+/// is from when the child is spawned until it's reaped, so a child that's reused for several
+/// records reports its whole lifetime, not the time spent on any one record.
+///
+/// Since this reaps children itself, it has to directly wrap a `Command`. Other spawners should
+/// wrap this instead.
+#[derive(Debug)]
+pub struct JobLog<C, W> {
+    command: C,
+    out: W,
+    starts: HashMap<u32, Instant>,
+}
+
+impl<C: BorrowMut<Command>, W: Write> JobLog<C, W> {
+    /// Log the children spawned from `command` to `out`, starting with the header
+    ///
+    /// # Errors
+    ///
+    /// If the header can't be written.
+    pub fn new(command: C, mut out: W) -> io::Result<Self> {
+        out.write_all(HEADER.as_bytes())?;
+        Ok(JobLog {
+            command,
+            out,
+            starts: HashMap::new(),
+        })
+    }
+}
+
+impl<C: BorrowMut<Command>, W: Write> Spawn for JobLog<C, W> {
+    fn command(&mut self) -> &mut Command {
+        self.command.borrow_mut()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        let child = Spawn::spawn(&mut self.command, slot)?;
+        self.starts.insert(child.id(), Instant::now());
+        Ok(child)
+    }
+
+    fn wait(&mut self, mut child: Child) -> Result<(), Error> {
+        // close stdin so the child can finish
+        drop(child.stdin.take());
+        let pid = child.id();
+        let (status, usage) = wait4(pid).map_err(Error::Wait)?;
+        let end = Instant::now();
+        let elapsed = self
+            .starts
+            .remove(&pid)
+            .map_or(Duration::ZERO, |start| end.duration_since(start));
+        let line = format!(
+            "{pid}\t{}\t{}\t{:.3}\t{:.3}\t{:.3}\t{}\n",
+            status.code().unwrap_or(-1),
+            status.signal().unwrap_or(0),
+            elapsed.as_secs_f64(),
+            seconds(usage.ru_utime),
+            seconds(usage.ru_stime),
+            max_rss_kib(&usage),
+        );
+        self.out.write_all(line.as_bytes()).map_err(Error::JobLog)?;
+        match status.code() {
+            Some(0) => Ok(()),
+            Some(code) => Err(Error::NonZeroExitCode(code)),
+            None => Err(Error::KilledBySignal),
+        }
+    }
+}
+
+/// Wait for the child `pid` to exit, returning its status and resource usage
+fn wait4(pid: u32) -> io::Result<(ExitStatus, libc::rusage)> {
+    let pid = libc::pid_t::try_from(pid).map_err(io::Error::other)?;
+    let mut status = 0;
+    let mut usage = MaybeUninit::uninit();
+    loop {
+        // SAFETY: the pointers are valid, and usage is initialized if wait4 succeeds
+        unsafe {
+            if libc::wait4(pid, &raw mut status, 0, usage.as_mut_ptr()) >= 0 {
+                return Ok((ExitStatus::from_raw(status), usage.assume_init()));
+            }
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Convert a timeval to seconds
+#[allow(clippy::cast_precision_loss)]
+fn seconds(time: libc::timeval) -> f64 {
+    time.tv_sec as f64 + time.tv_usec as f64 / 1e6
+}
+
+/// The maximum resident set size in kibibytes
+fn max_rss_kib(usage: &libc::rusage) -> libc::c_long {
+    // macos reports this in bytes instead of kibibytes
+    if cfg!(target_os = "macos") {
+        usage.ru_maxrss / 1024
+    } else {
+        usage.ru_maxrss
+    }
+}
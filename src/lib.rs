@@ -14,21 +14,182 @@
 //!
 //! let mut input = // ...
 //! # BufReader::new(&[0_u8; 0][..]);
-//! // Spawn up to two `cat` processes, could also use `Rotating`
+//! // Spawn up to two `cat` processes, could also use `Rotating` or `Random`
 //! let mut pool = Limiting::new(Command::new("cat"), 2);
 //! xstream_util::xstream(&mut pool, &mut input, &b"\n", &None::<&[u8]>).unwrap();
 //! ```
 #![warn(missing_docs)]
 #![warn(clippy::pedantic)]
 
+mod audit;
+#[cfg(target_os = "linux")]
+mod cgroup;
+mod checkpoint;
+mod collect;
+mod decode;
+mod dedup;
+mod detect;
+mod framing;
+#[cfg(unix)]
+mod joblog;
 mod limit;
+#[cfg(unix)]
+mod lock;
+mod manifest;
+#[cfg(target_os = "linux")]
+mod notify;
+mod part;
+mod payload;
+mod pidfile;
+mod plan;
 mod pool;
+mod prefix;
+mod progress;
+#[cfg(unix)]
+mod pty;
+mod random;
+#[cfg(unix)]
+mod record;
 mod rot;
+mod route;
+mod routed;
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+mod sandbox;
+mod sniff;
+#[cfg(unix)]
+mod spool;
+mod stats;
+mod tee;
+#[cfg(feature = "otel")]
+mod trace;
+#[cfg(feature = "encoding")]
+mod transcode;
 
+pub use audit::{read_audit, read_setup, Audit, Delivery, Setup, Task};
+#[cfg(target_os = "linux")]
+pub use cgroup::Cgroup;
+pub use checkpoint::{Checkpoint, Routing};
+pub use collect::Collected;
+pub use decode::Decoding;
+pub use dedup::Dedup;
+pub use detect::detect_delimiter;
+pub use framing::Framing;
+#[cfg(unix)]
+pub use joblog::JobLog;
 pub use limit::Limiting;
-pub use pool::{Error, Pool};
+#[cfg(unix)]
+pub use lock::Locked;
+pub use manifest::{read_manifest, Entry, Manifest};
+#[cfg(target_os = "linux")]
+pub use notify::Notifier;
+pub use part::Partitioned;
+pub use payload::Payloads;
+pub use pidfile::PidFile;
+pub use plan::{plan, Assignment, Plan, PoolSpec};
+pub use pool::{Error, Pool, Spawn};
+pub use prefix::Prefixed;
+pub use progress::{Dashboard, Progress, Watched};
+#[cfg(unix)]
+pub use pty::Pty;
+pub use random::Random;
+#[cfg(unix)]
+pub use record::RecordEnv;
+#[cfg(target_os = "linux")]
+pub use record::{RecordFile, RECORD_FD};
 pub use rot::Rotating;
+pub use route::{ParseRouteError, Route};
+pub use routed::Routed;
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+pub use sandbox::Sandbox;
+pub use sniff::{sniff_format, Format};
+#[cfg(unix)]
+pub use spool::Spool;
+pub use stats::Stats;
 use std::io::{BufRead, Write};
+pub use tee::Tee;
+#[cfg(feature = "otel")]
+pub use trace::Traced;
+#[cfg(feature = "encoding")]
+pub use transcode::Transcoder;
+
+/// Find how much of `buf` can be consumed before hitting `delim`
+///
+/// Returns the number of bytes to consume, and whether they end with the delimiter.
+fn find_delim(buf: &[u8], delim: &[u8]) -> (usize, bool) {
+    match buf.windows(delim.len()).position(|w| w == delim) {
+        // no match
+        None => (
+            if buf.len() < delim.len() {
+                // buffer can never contain the match, so dump the rest
+                buf.len()
+            } else {
+                // write we can to guarantee we didn't write part of a match
+                buf.len() - delim.len() + 1
+            },
+            false,
+        ),
+        // matched write up to match, consume the match
+        Some(pos) => (pos + delim.len(), true),
+    }
+}
+
+/// Read a single record delimited by `delim` into `buf`
+///
+/// This is similar to `BufRead::read_until`, but for multi-byte delimiters. All bytes up to and
+/// including the delimiter are appended to `buf`, and the number of bytes read is returned. The
+/// last record may not end with the delimiter, and zero bytes are read at the end of the input.
+///
+/// # Errors
+///
+/// If there are problems reading from `in_handle`.
+pub fn read_record(
+    in_handle: &mut impl BufRead,
+    delim: impl AsRef<[u8]>,
+    buf: &mut Vec<u8>,
+) -> Result<usize, Error> {
+    let delim = delim.as_ref();
+    let mut read = 0;
+    loop {
+        let chunk = in_handle.fill_buf().map_err(Error::Input)?;
+        let (consume, hit_delim) = find_delim(chunk, delim);
+        buf.extend_from_slice(&chunk[..consume]);
+        in_handle.consume(consume);
+        read += consume;
+        if hit_delim || consume == 0 {
+            return Ok(read);
+        }
+    }
+}
+
+/// Read a single record delimited by `delim` into `buf`, ignoring delimiters inside double quotes
+///
+/// This is like [`read_record`], but for CSV, where quoted fields can contain the delimiter.
+/// Quotes are escaped by doubling them, so a delimiter only ends a record after an even number of
+/// quotes.
+///
+/// # Errors
+///
+/// If there are problems reading from `in_handle`.
+// records are scanned once for their delimiter anyway, so counting doesn't need to be fast
+#[allow(clippy::naive_bytecount)]
+pub fn read_quoted_record(
+    in_handle: &mut impl BufRead,
+    delim: impl AsRef<[u8]>,
+    buf: &mut Vec<u8>,
+) -> Result<usize, Error> {
+    let delim = delim.as_ref();
+    let mut read = 0;
+    let mut quotes = 0;
+    loop {
+        let start = buf.len();
+        let chunk = read_record(in_handle, delim, buf)?;
+        read += chunk;
+        quotes += buf[start..].iter().filter(|&&b| b == b'"').count();
+        if chunk == 0 || quotes % 2 == 0 || !buf.ends_with(delim) {
+            return Ok(read);
+        }
+    }
+}
 
 /// Stream one reader into several independent processes
 ///
@@ -58,21 +219,7 @@ pub fn xstream(
 
         while {
             let buf = in_handle.fill_buf().map_err(Error::Input)?;
-            let (consume, hit_delim) = match buf.windows(delim.len()).position(|w| w == delim) {
-                // no match
-                None => (
-                    if buf.len() < delim.len() {
-                        // buffer can never contain the match, so dump the rest
-                        buf.len()
-                    } else {
-                        // write we can to guarantee we didn't write part of a match
-                        buf.len() - delim.len() + 1
-                    },
-                    false,
-                ),
-                // matched write up to match, consume the match
-                Some(pos) => (pos + delim.len(), true),
-            };
+            let (consume, hit_delim) = find_delim(buf, delim);
             if let (Some(wdel), true) = (write_delim, hit_delim) {
                 out_handle
                     .write_all(&buf[..consume - delim.len()])
@@ -4,11 +4,10 @@
 //! so there are no zombie processes. When trying to spawn more than the limit, the old process
 //! will be waited on before spawning a new one. To effectively manage cleanup, this needs to be
 //! dropped, so panics while using this may result in zombie processes.
-use super::pool;
-use super::pool::{Error, Pool};
-use std::borrow::BorrowMut;
+use super::pool::{Error, Pool, Spawn};
 use std::collections::VecDeque;
-use std::process::{Child, Command, Stdio};
+use std::mem;
+use std::process::Child;
 
 // TODO implement a better limited pool that pipes to the next completed one
 /// A pool to manage spawning a limited number of processses
@@ -17,27 +16,28 @@ use std::process::{Child, Command, Stdio};
 /// schedule a long running process, then a bunch of short ones, it won't schedule more short ones
 /// beyond the buffer until the long one has finished.
 #[derive(Debug)]
-pub struct Limiting<C> {
+pub struct Limiting<S> {
     procs: VecDeque<Child>,
     max_procs: usize,
-    command: C,
+    spawner: S,
+    spawned: usize,
 }
 
-impl<C: BorrowMut<Command>> Limiting<C> {
+impl<S: Spawn> Limiting<S> {
     /// Create a new empty pool with a limited number of total processes
     ///
-    /// Set `max_procs` to 0 to enable unbounded parallelism.
-    pub fn new(mut command: C, max_procs: usize) -> Self {
-        command.borrow_mut().stdin(Stdio::piped());
+    /// `spawner` is usually a `Command`. Set `max_procs` to 0 to enable unbounded parallelism.
+    pub fn new(spawner: S, max_procs: usize) -> Self {
         Limiting {
             procs: VecDeque::with_capacity(max_procs),
             max_procs,
-            command,
+            spawner,
+            spawned: 0,
         }
     }
 }
 
-impl<C: BorrowMut<Command>> Pool for Limiting<C> {
+impl<S: Spawn> Pool for Limiting<S> {
     /// Spawn a new process with command and return a mutable reference to the process
     ///
     /// This command will block until it can schedule the process under the constraints. It can
@@ -47,15 +47,44 @@ impl<C: BorrowMut<Command>> Pool for Limiting<C> {
     fn get(&mut self) -> Result<&mut Child, Error> {
         // wait for the oldest process if we're bounded
         if self.max_procs != 0 && self.procs.len() == self.max_procs {
-            pool::wait_proc(self.procs.pop_front().unwrap())?;
+            self.spawner.wait(self.procs.pop_front().unwrap())?;
         };
 
         // now schedule new process
-        let proc = self.command.borrow_mut().spawn().map_err(Error::Spawn)?;
+        let slot = if self.max_procs == 0 {
+            self.spawned
+        } else {
+            self.spawned % self.max_procs
+        };
+        let proc = self.spawner.spawn(slot)?;
+        self.spawned += 1;
         self.procs.push_back(proc);
         Ok(self.procs.back_mut().unwrap()) // just pushed
     }
 
+    /// The slot of the newest process
+    ///
+    /// New processes replace the oldest, so slots are reused in a round-robin order.
+    fn slot(&self) -> usize {
+        let slot = self.spawned.saturating_sub(1);
+        if self.max_procs == 0 {
+            slot
+        } else {
+            slot % self.max_procs
+        }
+    }
+
+    /// Replace the newest process
+    fn respawn(&mut self) -> Result<&mut Child, Error> {
+        if self.procs.is_empty() {
+            return self.get();
+        }
+        let proc = self.spawner.spawn(self.slot())?;
+        let old = mem::replace(self.procs.back_mut().unwrap(), proc); // not empty
+        self.spawner.wait(old)?;
+        Ok(self.procs.back_mut().unwrap()) // just replaced
+    }
+
     /// Wait for all processes to finish
     ///
     /// Errors will terminate early and not wait for reamining processes to finish. To continue
@@ -65,13 +94,13 @@ impl<C: BorrowMut<Command>> Pool for Limiting<C> {
         // NOTE we do this instead of drain so that errors don't drop the rest of our processes
         // creating zombies
         while let Some(proc) = self.procs.pop_back() {
-            pool::wait_proc(proc)?;
+            self.spawner.wait(proc)?;
         }
         Ok(())
     }
 }
 
-impl<C> Drop for Limiting<C> {
+impl<S> Drop for Limiting<S> {
     fn drop(&mut self) {
         // kill any children left in self
         for proc in &mut self.procs {
@@ -0,0 +1,114 @@
+//! Named locks for child processes
+//!
+//! Parallel children sometimes touch a shared resource, like an index file or a database, that
+//! only one of them can use at a time. This makes every child wait for an exclusive lock on a file
+//! before it runs, and hold it until it exits.
+use super::pool::{Error, Spawn};
+use std::env;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+/// A spawner whose children hold an exclusive lock on a file while they run
+///
+/// Every child gets its own descriptor for the lock file, and waits for the lock before it execs,
+/// so spawning blocks until the lock is free. Children should therefore be sent all of their input
+/// and have their stdin closed before the next one is spawned.
+#[derive(Debug)]
+pub struct Locked<S> {
+    inner: S,
+    path: PathBuf,
+    fd: Arc<AtomicI32>,
+}
+
+impl<S: Spawn> Locked<S> {
+    /// Wrap `inner` so that its children lock the file at `path`, creating it if necessary
+    ///
+    /// # Errors
+    ///
+    /// If `path` is a symbolic link, or the file can't be created.
+    pub fn new(mut inner: S, path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        // create the file first, so children can lock it without write access
+        open(OpenOptions::new().append(true).create(true), &path)?;
+        let fd = Arc::new(AtomicI32::new(-1));
+        let child_fd = Arc::clone(&fd);
+        // SAFETY: flock and fcntl are async signal safe
+        unsafe {
+            inner.command().pre_exec(move || {
+                let fd = child_fd.load(Ordering::SeqCst);
+                while libc::flock(fd, libc::LOCK_EX) < 0 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                // keep the descriptor, and therefore the lock, open until the child exits
+                if libc::fcntl(fd, libc::F_SETFD, 0) < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            });
+        }
+        Ok(Locked { inner, path, fd })
+    }
+
+    /// Wrap `inner` so that its children lock the file `name`
+    ///
+    /// The file is in `xstream-<uid>` in the temporary directory, which only the current user can
+    /// access, and is created if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// If the directory exists but isn't private to the current user, or the directory or the
+    /// file can't be created.
+    pub fn named(inner: S, name: &str) -> io::Result<Self> {
+        // SAFETY: getuid is always successful
+        let uid = unsafe { libc::getuid() };
+        let dir = env::temp_dir().join(format!("xstream-{uid}"));
+        match DirBuilder::new().mode(0o700).create(&dir) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                let meta = fs::symlink_metadata(&dir)?;
+                if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+                    return Err(io::Error::other(format!(
+                        "{} isn't a directory private to the current user",
+                        dir.display()
+                    )));
+                }
+            }
+            res => res?,
+        }
+        Self::new(inner, dir.join(format!("{name}.lock")))
+    }
+}
+
+impl<S: Spawn> Spawn for Locked<S> {
+    fn command(&mut self) -> &mut Command {
+        self.inner.command()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        let file = open(OpenOptions::new().read(true), &self.path).map_err(Error::Spawn)?;
+        self.fd.store(file.as_raw_fd(), Ordering::SeqCst);
+        // NOTE our copy of the file is closed once the child has its own
+        self.inner.spawn(slot)
+    }
+
+    fn wait(&mut self, child: Child) -> Result<(), Error> {
+        self.inner.wait(child)
+    }
+}
+
+/// Open the file at `path` without following symbolic links
+///
+/// Files are always opened with close on exec, so children only keep the descriptor they lock.
+fn open(options: &mut OpenOptions, path: &Path) -> io::Result<File> {
+    options.custom_flags(libc::O_NOFOLLOW).open(path)
+}
@@ -4,12 +4,69 @@
 //! much more convenient.
 #![warn(clippy::pedantic)]
 
-use clap::builder::NonEmptyStringValueParser;
-use clap::{ArgGroup, Parser};
-use std::io;
-use std::process::Command;
+use clap::builder::{
+    NonEmptyStringValueParser, PossibleValuesParser, RangedU64ValueParser, TypedValueParser,
+};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgGroup, ArgMatches, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+#[cfg(feature = "otel")]
+use opentelemetry::global::{self, BoxedTracer};
+#[cfg(feature = "otel")]
+use opentelemetry::trace::{Span, Status, Tracer};
+#[cfg(feature = "otel")]
+use opentelemetry::KeyValue;
+#[cfg(feature = "otel")]
+use opentelemetry_otlp::SpanExporter;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use regex::bytes::Regex;
+#[cfg(target_os = "linux")]
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::env;
+#[cfg(unix)]
+use std::ffi::CString;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, LineWriter, Read, Write};
+#[cfg(unix)]
+use std::io::{Seek, SeekFrom};
+use std::iter;
+#[cfg(unix)]
+use std::mem::MaybeUninit;
+#[cfg(unix)]
+use std::os::fd::{AsFd, AsRawFd};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+#[cfg(unix)]
+use std::ptr;
+use std::rc::Rc;
 use std::str;
-use xstream_util::{Limiting, Rotating};
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use toml::{Table, Value};
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+use xstream_util::Sandbox;
+#[cfg(feature = "otel")]
+use xstream_util::Traced;
+#[cfg(feature = "encoding")]
+use xstream_util::Transcoder;
+use xstream_util::{
+    read_audit, read_setup, Audit, Checkpoint, Collected, Dashboard, Decoding, Dedup, Delivery,
+    Error, Format, Framing, Limiting, Manifest, Partitioned, Payloads, PidFile, Pool, Prefixed,
+    Random, Rotating, Route, Routed, Routing, Setup, Spawn, Stats, Task, Tee,
+};
+#[cfg(unix)]
+use xstream_util::{read_manifest, JobLog, Locked, Pty, RecordEnv, Spool};
+#[cfg(target_os = "linux")]
+use xstream_util::{Cgroup, Notifier, RecordFile, RECORD_FD};
 
 /// Escape delimiters in a string
 ///
@@ -47,6 +104,374 @@ fn unescape_delimiter(char_ref: impl AsRef<str>) -> String {
     res
 }
 
+/// Fixed bytes to write to the stdin of every child
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Payload(Vec<u8>);
+
+/// Parse a payload that's either an escaped string, or `@` followed by a file to read
+fn parse_payload(payload: &str) -> Result<Payload, String> {
+    match payload.strip_prefix('@') {
+        Some(path) => fs::read(path)
+            .map(Payload)
+            .map_err(|err| format!("couldn't read {path}: {err}")),
+        None => Ok(Payload(unescape_delimiter(payload).into_bytes())),
+    }
+}
+
+/// Parse a size in bytes with an optional binary suffix (k, m, g, or t)
+fn parse_size(size: &str) -> Result<u64, String> {
+    let lower = size.trim().to_ascii_lowercase();
+    let digits = lower.trim_end_matches(['b', 'i']);
+    let (num, scale) = match digits.char_indices().last() {
+        Some((ind, 'k')) => (&digits[..ind], 1 << 10),
+        Some((ind, 'm')) => (&digits[..ind], 1 << 20),
+        Some((ind, 'g')) => (&digits[..ind], 1 << 30),
+        Some((ind, 't')) => (&digits[..ind], 1 << 40),
+        _ => (digits, 1),
+    };
+    let val: u64 = num
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size: {size}"))?;
+    val.checked_mul(scale)
+        .ok_or_else(|| format!("size too large: {size}"))
+}
+
+/// Parse a positive number of cpus
+#[cfg(target_os = "linux")]
+fn parse_cpus(cpus: &str) -> Result<f64, String> {
+    match cpus.parse() {
+        Ok(val) if val > 0.0 => Ok(val),
+        _ => Err(format!("invalid number of cpus: {cpus}")),
+    }
+}
+
+/// Look up a character encoding by its label, e.g. latin1 or utf-16le
+#[cfg(feature = "encoding")]
+fn parse_encoding(label: &str) -> Result<&'static encoding_rs::Encoding, String> {
+    encoding_rs::Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| format!("unknown encoding: {label}"))
+}
+
+/// Parse how records are encoded
+fn decoding_parser() -> impl TypedValueParser<Value = Decoding> {
+    PossibleValuesParser::new(["base64", "hex"]).map(|decoding| match decoding.as_str() {
+        "base64" => Decoding::Base64,
+        _ => Decoding::Hex,
+    })
+}
+
+/// Parse how records are framed
+fn framing_parser() -> impl TypedValueParser<Value = Framing> {
+    PossibleValuesParser::new(["netstring", "u32le", "varint"]).map(|framing| {
+        match framing.as_str() {
+            "netstring" => Framing::Netstring,
+            "u32le" => Framing::U32Le,
+            _ => Framing::Varint,
+        }
+    })
+}
+
+/// Parse a routing pattern, which must have a capture group for the key
+fn parse_route(pattern: &str) -> Result<Regex, String> {
+    let regex = Regex::new(pattern).map_err(|err| err.to_string())?;
+    if regex.captures_len() < 2 {
+        Err(format!("pattern has no capture group: {pattern}"))
+    } else {
+        Ok(regex)
+    }
+}
+
+/// Parse a routing expression
+fn parse_route_expr(expr: &str) -> Result<Route, String> {
+    expr.parse()
+        .map_err(|err| format!("invalid route {expr:?}: {err}"))
+}
+
+/// A named set of options from the config file
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Profile {
+    /// The long name of each option, and the arguments that set it
+    args: Vec<(String, Vec<String>)>,
+    /// The command to run if none is given on the command line
+    command: Vec<String>,
+}
+
+/// The default location of the config file
+fn default_config() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("xstream").join("config.toml"))
+}
+
+/// Load the profile called `name` from the config file at `config`
+fn load_profile(config: &Path, name: &str) -> Result<Profile, String> {
+    let contents = fs::read_to_string(config)
+        .map_err(|err| format!("couldn't read config {}: {err}", config.display()))?;
+    let table: Table = contents
+        .parse()
+        .map_err(|err| format!("invalid config {}: {err}", config.display()))?;
+    let profile = table
+        .get("profiles")
+        .and_then(Value::as_table)
+        .and_then(|profiles| profiles.get(name))
+        .and_then(Value::as_table)
+        .ok_or_else(|| format!("no profile named {name} in {}", config.display()))?;
+    parse_profile(profile)
+}
+
+/// Convert a profile table into options
+///
+/// Every key is the name of a long option. Booleans are flags, arrays are repeated options, and
+/// the special key `command` is the command to run.
+fn parse_profile(table: &Table) -> Result<Profile, String> {
+    let mut profile = Profile::default();
+    for (key, val) in table {
+        if key == "command" {
+            profile.command = match val {
+                Value::String(command) => vec![command.clone()],
+                Value::Array(command) => command
+                    .iter()
+                    .map(|arg| arg.as_str().map(str::to_owned))
+                    .collect::<Option<_>>()
+                    .ok_or("profile command must only contain strings")?,
+                _ => return Err("profile command must be a string or array".into()),
+            };
+            continue;
+        }
+        let vals = match val {
+            Value::Array(vals) => vals.as_slice(),
+            val => std::slice::from_ref(val),
+        };
+        let mut args = Vec::with_capacity(vals.len());
+        for val in vals {
+            match val {
+                Value::Boolean(true) => args.push(format!("--{key}")),
+                Value::Boolean(false) => {}
+                Value::String(val) => args.push(format!("--{key}={val}")),
+                Value::Integer(val) => args.push(format!("--{key}={val}")),
+                Value::Float(val) => args.push(format!("--{key}={val}")),
+                _ => return Err(format!("unsupported value for {key} in profile")),
+            }
+        }
+        profile.args.push((key.clone(), args));
+    }
+    Ok(profile)
+}
+
+/// A user that children should run as
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct User {
+    uid: libc::uid_t,
+    gid: Option<libc::gid_t>,
+}
+
+/// Look up a user by name or numeric id
+///
+/// Numeric ids don't need to exist, but then they won't have a primary group.
+#[cfg(unix)]
+fn parse_user(user: &str) -> Result<User, String> {
+    let name = CString::new(user).map_err(|_| format!("invalid user: {user}"))?;
+    let numeric = user.parse().ok();
+    // SAFETY: the returned entry is only read before any other call to getpw*
+    unsafe {
+        let entry = match numeric {
+            Some(uid) => libc::getpwuid(uid),
+            None => libc::getpwnam(name.as_ptr()),
+        };
+        match (entry.as_ref(), numeric) {
+            (Some(entry), _) => Ok(User {
+                uid: entry.pw_uid,
+                gid: Some(entry.pw_gid),
+            }),
+            (None, Some(uid)) => Ok(User { uid, gid: None }),
+            (None, None) => Err(format!("unknown user: {user}")),
+        }
+    }
+}
+
+/// Look up a group by name or numeric id
+#[cfg(unix)]
+fn parse_group(group: &str) -> Result<libc::gid_t, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(group).map_err(|_| format!("invalid group: {group}"))?;
+    // SAFETY: the returned entry is only read before any other call to getgr*
+    unsafe {
+        libc::getgrnam(name.as_ptr())
+            .as_ref()
+            .map(|entry| entry.gr_gid)
+            .ok_or_else(|| format!("unknown group: {group}"))
+    }
+}
+
+/// How records are assigned to reused processes
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Strategy {
+    /// Cycle through processes in order
+    RoundRobin,
+    /// Pick a uniformly random process for every record once every process is spawned
+    Random,
+}
+
+/// Formats that input can be split in
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    /// Guess from the start of the input, and print what was chosen and why
+    Auto,
+    /// Lines, except for line breaks inside quoted fields
+    Csv,
+}
+
+/// What to do when a child closes its stdin before it has been sent an entire record
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum BrokenPipe {
+    /// Stop splitting and fail
+    Fail,
+    /// Replace the child and resend the record once, then drop it and continue with the next one
+    Skip,
+}
+
+/// Restrictions that can be applied to children
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum SandboxPreset {
+    /// Children can't open network sockets
+    NoNetwork,
+    /// Children can only write beneath the sandbox directories
+    ReadOnly,
+    /// Both no-network and read-only
+    Strict,
+}
+
+/// The process pools that can be benchmarked
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum BenchPool {
+    /// A new process for every record
+    Limiting,
+    /// Reused processes in a round-robin order
+    Rotating,
+    /// Reused processes picked at random
+    Random,
+    /// Reused processes picked by key
+    Partitioned,
+}
+
+/// Options for the bench subcommand
+#[derive(Args, Debug)]
+struct BenchArgs {
+    /// The number of records to generate
+    #[clap(long, value_name = "RECORDS", default_value_t = 10_000)]
+    records: usize,
+
+    /// The size of each generated record, not including the newline delimiter, e.g. 100 or 4k
+    #[clap(long, value_name = "SIZE", value_parser = parse_size, default_value = "1k")]
+    record_size: u64,
+
+    /// Benchmark with this many parallel processes
+    ///
+    /// Can be specified multiple times, defaults to the number of available cpus.
+    #[clap(
+        short,
+        long,
+        value_name = "PROCS",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    parallel: Vec<usize>,
+
+    /// Only benchmark this pool
+    ///
+    /// Can be specified multiple times, defaults to every pool.
+    #[clap(long, value_enum)]
+    pool: Vec<BenchPool>,
+
+    /// The command to send records to, defaults to cat
+    ///
+    /// The output of the command is discarded.
+    #[clap(value_parser = NonEmptyStringValueParser::new())]
+    command: Vec<String>,
+}
+
+/// Options for the serve subcommand
+#[cfg(unix)]
+#[derive(Args, Debug)]
+struct ServeArgs {
+    /// The path of the unix socket to listen on
+    #[clap(long, value_name = "PATH")]
+    socket: PathBuf,
+
+    /// Keep the routing state of the pool and the number of records sent to each worker here
+    ///
+    /// The checkpoint is read when serving starts, if it exists, and rewritten after every
+    /// stream, so a restarted server keeps sending keys to the same workers. The number of
+    /// processes comes from the checkpoint, and its offset counts the bytes of every stream.
+    #[clap(long, value_name = "PATH")]
+    checkpoint: Option<PathBuf>,
+
+    /// The options and command to split each stream with, as if they were passed to xstream
+    #[clap(
+        value_name = "ARGS",
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    args: Vec<OsString>,
+}
+
+/// Options for the replay subcommand
+#[derive(Args, Debug)]
+struct ReplayArgs {
+    /// Send every record to the same worker it was originally sent to
+    ///
+    /// This starts --parallel processes, which must be at least the number of original workers.
+    #[clap(long)]
+    same_workers: bool,
+
+    /// The audit directory to replay
+    #[clap(value_name = "DIR")]
+    dir: PathBuf,
+
+    /// The options and command to send the records to, as if they were passed to xstream
+    #[clap(
+        value_name = "ARGS",
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    args: Vec<OsString>,
+}
+
+/// Tools that don't split stdin
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// Measure how quickly a synthetic stream can be split among processes
+    ///
+    /// Every combination of pool and number of parallel processes is run against the same
+    /// generated input, and the throughput of each is reported.
+    Bench(BenchArgs),
+    /// Keep processes running and split every stream sent to a unix socket among them
+    ///
+    /// Each connection to the socket is a separate input stream, which is split with the options
+    /// that follow the socket, e.g. `xstream serve --socket /run/xstream.sock -r -p 4 -- cat`.
+    /// Processes aren't restarted between streams, and the last record of each stream is always
+    /// followed by a delimiter so that records from different streams are never joined.
+    ///
+    /// On SIGTERM, xstream stops reading streams and exits once every process has finished. When
+    /// started by systemd with `Type=notify`, it reports when it's ready and stopping, and pings
+    /// the watchdog if one is configured, as long as it isn't stuck writing to a process.
+    #[cfg(unix)]
+    Serve(ServeArgs),
+    /// Send the records saved by --audit to new processes
+    ///
+    /// Records are sent exactly as they were originally, in the same order, so options that
+    /// change how records are read or written don't apply, e.g.
+    /// `xstream replay --same-workers audit -r -p 4 -- cat`. The --init-stdin and --final-stdin
+    /// payloads are restored from the audit unless they're given again, and so is passing records
+    /// with --record-env or --record-file.
+    Replay(ReplayArgs),
+}
+
 /// Split a stream among several processes
 ///
 /// xstream splits stdin by a given delimiter and pipes each section into a new process as the
@@ -55,7 +480,15 @@ fn unescape_delimiter(char_ref: impl AsRef<str>) -> String {
 /// processes is limited. You can opt to reuse processes in a round-robin manner with the `--reuse`
 /// option.
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[allow(clippy::struct_excessive_bools)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_override_self = true,
+    args_conflicts_with_subcommands = true
+)]
 #[clap(group(ArgGroup::new("delim").arg("null").conflicts_with("delimiter")))]
 struct Cli {
     /// Set the delimiter between inputs
@@ -80,6 +513,166 @@ struct Cli {
     #[clap(short, long)]
     write_delimiter: Option<String>,
 
+    /// Guess the delimiter from the start of the input
+    ///
+    /// Chooses among \0, the ascii record separator (0x1E), \r\n, \n, and \t by how often they
+    /// appear, and prints the choice to stderr. If none appear the delimiter is left as \n.
+    #[clap(long, conflicts_with_all = ["delimiter", "delim", "framing"])]
+    detect_delimiter: bool,
+
+    /// Split input in this format instead of by a delimiter
+    ///
+    /// Guessing chooses among JSON lines, CSV with quoted fields, length prefixed framing, and
+    /// text with a delimiter like --detect-delimiter. CSV requires reading each record into memory.
+    #[clap(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        conflicts_with_all = ["delimiter", "delim", "framing", "detect_delimiter"]
+    )]
+    format: Option<InputFormat>,
+
+    /// Split the output of this shell command instead of stdin
+    ///
+    /// xstream fails if the command fails, after every record it wrote has been processed.
+    #[clap(long, value_name = "COMMAND")]
+    input_cmd: Option<String>,
+
+    /// Write an exact copy of the input to this file as it's split
+    ///
+    /// This is the raw input, before any conversion. When serving, every stream is appended.
+    #[clap(long, value_name = "PATH")]
+    tee_input: Option<PathBuf>,
+
+    /// Keep reading input into a file in this directory while children are busy
+    ///
+    /// Input is read as soon as it's available, and records are split from the spool as children
+    /// catch up, so producers like network sockets don't time out when children are slow for a
+    /// while. The file is removed as soon as it's created, and only applies to stdin or
+    /// --input-cmd.
+    #[cfg(unix)]
+    #[clap(long, value_name = "DIR")]
+    spool: Option<PathBuf>,
+
+    /// The most input the spool can hold, after which reading input waits for children
+    #[cfg(unix)]
+    #[clap(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        default_value = "1G",
+        requires = "spool"
+    )]
+    spool_max: u64,
+
+    /// Write a manifest of every record sent to a child to this file
+    ///
+    /// The manifest is tab separated with a header, and has a line for every record with its
+    /// sequence number, the byte offset and length it was read from in the input including any
+    /// delimiter or framing, the XXH64 checksum of what was sent to the child, and the worker it
+    /// was sent to. Workers are numbered by their slot in the pool, from zero up to --parallel, and
+    /// a child that replaces one that exited takes its number. This requires reading each record
+    /// into memory.
+    #[clap(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Write an exact copy of every record sent to a child to its own file in this directory
+    ///
+    /// Files are named by the sequence number of the record and the worker it was sent to, like
+    /// the manifest, so a misbehaving child's input can be reproduced without rerunning
+    /// everything. This requires reading each record into memory.
+    #[clap(long, value_name = "DIR")]
+    audit: Option<PathBuf>,
+
+    /// Export OpenTelemetry spans for every child and record with OTLP over HTTP
+    ///
+    /// Each child has a span from when it's spawned until it exits, with its exit code, and each
+    /// record has a span from when it's read until it's sent, with its size, whether it was sent or
+    /// dropped, and the pid and worker of the child it was sent to. Workers are numbered like in
+    /// --manifest. A child usually outlives the records it's sent, so record spans don't have an
+    /// exit code; it's on the span of the child instead. The exporter is configured with the
+    /// standard `OTEL_EXPORTER_OTLP_*` environment variables. This requires reading each record
+    /// into memory.
+    #[cfg(feature = "otel")]
+    #[clap(long)]
+    otel: bool,
+
+    /// Show a live dashboard of progress on stderr, which must be a terminal
+    ///
+    /// The dashboard has the input read, the records sent and dropped, the children that failed,
+    /// the data sent to and throughput of each worker, and recent errors. Workers are numbered
+    /// like in --manifest, and only the first 16 are shown. The percentage of input read is only
+    /// shown when stdin is a file. This requires reading each record into memory.
+    #[clap(long)]
+    tui: bool,
+
+    /// Only split the records that the manifest says were sent to this worker
+    ///
+    /// The manifest is read instead of written, and stdin must be the same seekable file that was
+    /// split when it was written. This makes it possible to reprocess a single worker's records.
+    #[cfg(unix)]
+    #[clap(
+        long,
+        value_name = "WORKER",
+        requires = "manifest",
+        conflicts_with = "input_cmd"
+    )]
+    #[cfg_attr(feature = "encoding", clap(conflicts_with = "from_encoding"))]
+    rerun_partition: Option<usize>,
+
+    /// Read records that are prefixed by their length instead of split by a delimiter
+    ///
+    /// Records are written to children without the framing, followed by the write delimiter if
+    /// one is specified. This requires reading each record into memory.
+    #[clap(
+        long,
+        value_name = "FRAMING",
+        value_parser = framing_parser(),
+        conflicts_with = "delim"
+    )]
+    framing: Option<Framing>,
+
+    /// Write records to children prefixed by their length instead of followed by a delimiter
+    ///
+    /// This requires reading each record into memory.
+    #[clap(
+        long,
+        value_name = "FRAMING",
+        value_parser = framing_parser(),
+        conflicts_with = "write_delimiter"
+    )]
+    write_framing: Option<Framing>,
+
+    /// Convert the input from this character encoding to UTF-8 before splitting it
+    ///
+    /// Accepts any WHATWG encoding label, e.g. latin1, sjis, or utf-16le. The delimiter is
+    /// matched against the converted input.
+    #[cfg(feature = "encoding")]
+    #[clap(long, value_name = "ENCODING", value_parser = parse_encoding)]
+    from_encoding: Option<&'static encoding_rs::Encoding>,
+
+    /// Decode each record before writing it to a child
+    ///
+    /// The delimiter isn't decoded, and is still written after each decoded record. This requires
+    /// reading each record into memory.
+    #[clap(long, value_name = "ENCODING", value_parser = decoding_parser())]
+    decode: Option<Decoding>,
+
+    /// Skip records that are identical to the previous record
+    ///
+    /// This requires reading each record into memory.
+    #[clap(long)]
+    dedup_consecutive: bool,
+
+    /// Also skip records identical to any of this many recently seen distinct records
+    #[clap(
+        long,
+        value_name = "RECORDS",
+        default_value_t = 1,
+        requires = "dedup_consecutive"
+    )]
+    dedup_window: usize,
+
     /// Run up to this many processes in parallel
     ///
     /// Specifying 0 will spawn unlimited processes
@@ -90,35 +683,1670 @@ struct Cli {
     #[clap(short, long)]
     reuse: bool,
 
+    /// How to choose which reused process gets each record
+    #[clap(long, value_enum, default_value_t = Strategy::RoundRobin, requires = "reuse")]
+    strategy: Strategy,
+
+    /// What to do when a child stops reading its input before the end of a record
+    ///
+    /// Children like `head` may exit without reading everything they're sent. By default xstream
+    /// fails when that happens, but it can instead replace the child with a new one and send it
+    /// the record again. If the new child doesn't accept the record either, it's dropped, and the
+    /// number of dropped records is printed to stderr at the end.
+    #[clap(long, value_enum, value_name = "POLICY", default_value_t = BrokenPipe::Fail)]
+    on_broken_pipe: BrokenPipe,
+
+    /// Print a summary of the records that were split to stderr when done
+    ///
+    /// This includes the number of records and bytes sent to children, and percentiles of the
+    /// record sizes, which can explain why some children are busier than others.
+    #[clap(long)]
+    stats: bool,
+
+    /// Seed the random strategy so records are assigned reproducibly
+    ///
+    /// This can only be used with `--strategy random`.
+    #[clap(long, value_name = "SEED")]
+    seed: Option<u64>,
+
+    /// Send every record with the same key to the same process
+    ///
+    /// The key is the first capture group of this regular expression, and is hashed to pick one
+    /// of the parallel processes, which are reused. Records that don't match are sent to
+    /// processes in a round-robin order. This requires reading each record into memory.
+    #[clap(
+        long,
+        value_name = "PATTERN",
+        value_parser = parse_route,
+        conflicts_with = "strategy"
+    )]
+    route_regex: Option<Regex>,
+
+    /// Send every record to the process chosen by this routing expression
+    ///
+    /// For example `hash(field(2)) % workers` routes by the second whitespace separated field, and
+    /// `if len > 1M then worker(0) else rr()` sends large records to the first process and the
+    /// rest in a round-robin order. Expressions can use the record length, the number of workers,
+    /// fields separated by whitespace or a string as in field(n, ","), their hashes and numeric
+    /// values, arithmetic, comparisons, and, or, and if then else. `and` binds tighter than `or`,
+    /// and either side can decide them even if the other can't be evaluated. Records the
+    /// expression can't be evaluated for are sent in a round-robin order. This requires reading
+    /// each record into memory.
+    #[clap(
+        long,
+        value_name = "EXPR",
+        value_parser = parse_route_expr,
+        conflicts_with_all = ["strategy", "route_regex"]
+    )]
+    route: Option<Route>,
+
+    /// Write this to the stdin of every child before any records
+    ///
+    /// This is useful for sending a header or handshake to reused processes. Specify a file to
+    /// read the payload from by prefixing it with @. Otherwise this will unescape common
+    /// backslash escape sequences (0, a, b, v, f, n, r, t, e, and \).
+    #[clap(long, value_name = "STRING|@FILE", action = ArgAction::Set, value_parser = parse_payload)]
+    init_stdin: Option<Payload>,
+
+    /// Write this to the stdin of every child after all of its records
+    ///
+    /// This is useful for telling reused processes to flush or commit their results. It accepts
+    /// the same formats as --init-stdin.
+    #[clap(long, value_name = "STRING|@FILE", action = ArgAction::Set, value_parser = parse_payload)]
+    final_stdin: Option<Payload>,
+
+    /// Pass each record to its child in this environment variable instead of on stdin
+    ///
+    /// This is for commands that are configured with environment variables. Every record gets a
+    /// new child whose stdin is closed immediately, so this can't be combined with reusing
+    /// processes. Records containing a null character can't be passed.
+    #[cfg(unix)]
+    #[clap(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = [
+            "reuse",
+            "route_regex",
+            "route",
+            "init_stdin",
+            "final_stdin",
+            "write_framing",
+        ]
+    )]
+    record_env: Option<OsString>,
+
+    /// The largest record that can be passed with --record-env, larger records fail
+    #[cfg(unix)]
+    #[clap(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        default_value = "32k",
+        requires = "record_env"
+    )]
+    record_env_max: u64,
+
+    /// Pass each record to its child as a file instead of on stdin
+    ///
+    /// Each record is written to a sealed in-memory file, which the child can read from
+    /// /dev/fd/3. Every {} in the arguments is replaced with that path, or it's appended to them if
+    /// there isn't one. Every record gets a new child whose stdin is closed immediately, so this
+    /// can't be combined with reusing processes.
+    #[cfg(target_os = "linux")]
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "reuse",
+            "route_regex",
+            "route",
+            "init_stdin",
+            "final_stdin",
+            "write_framing",
+            "record_env",
+        ]
+    )]
+    record_file: bool,
+
+    /// Keep a file with the pid of xstream and every live child up to date
+    ///
+    /// The first line is the pid of xstream, and each following line is the pid of a child. The
+    /// file is removed when xstream exits.
+    #[clap(long, value_name = "PATH")]
+    pid_file: Option<PathBuf>,
+
+    /// Write the exit status and resource usage of every child to this file
+    ///
+    /// The file is tab separated with a header, and each line is written when a child exits. It
+    /// has the pid, exit code, signal, wall time, user and system cpu time in seconds, and the
+    /// maximum resident set size in kibibytes of the child. Reused children are logged once, with
+    /// the totals for their whole lifetime rather than for each record.
+    #[cfg(unix)]
+    #[clap(long, value_name = "PATH")]
+    joblog: Option<PathBuf>,
+
+    /// Only let one child with the same mutex name run at a time
+    ///
+    /// Each child waits for an exclusive lock on a file before it starts, and holds it until it
+    /// exits, so new children aren't spawned while one is running. Each child's stdin is closed
+    /// once it's sent its record, so nothing can be written to it afterwards with --final-stdin.
+    /// Names containing a / are used as the path to the lock file, otherwise it's created in a
+    /// directory in the temporary directory that only the current user can access.
+    #[cfg(unix)]
+    #[clap(long, value_name = "NAME", conflicts_with_all = ["reuse", "final_stdin"])]
+    mutex: Option<String>,
+
+    /// Run children as this user
+    ///
+    /// Accepts a user name or numeric id. Unless --group is also specified, children also run
+    /// with the primary group of the user. This usually requires running xstream as root.
+    #[cfg(unix)]
+    #[clap(long, value_name = "USER", value_parser = parse_user)]
+    user: Option<User>,
+
+    /// Run children as this group
+    ///
+    /// Accepts a group name or numeric id.
+    #[cfg(unix)]
+    #[clap(long, value_name = "GROUP", value_parser = parse_group)]
+    group: Option<libc::gid_t>,
+
+    /// Limit the cpu time of every child to this many seconds
+    ///
+    /// Children that exceed the limit are sent SIGXCPU by the kernel, and are killed if they use
+    /// another second. Unlike --cpu-max, this applies to each child individually, and counts
+    /// total cpu time rather than the rate of use.
+    #[cfg(unix)]
+    #[clap(
+        long,
+        value_name = "SECS",
+        value_parser = RangedU64ValueParser::<libc::rlim_t>::new().range(1..)
+    )]
+    cpu_limit: Option<libc::rlim_t>,
+
+    /// Attach the stdout of every child to a pseudo-terminal
+    ///
+    /// This makes programs that check if their output is a terminal behave as they would
+    /// interactively, e.g. line buffering their output. Their stdin is still a pipe.
+    #[cfg(unix)]
+    #[clap(long)]
+    pty: bool,
+
+    /// Prefix every line a child writes to stderr with the worker that wrote it
+    ///
+    /// Workers are numbered in the order they're spawned, and each line is tagged like
+    /// `[worker-N]`. Tags are colored when stderr is a terminal.
+    #[clap(long)]
+    prefix_stderr: bool,
+
+    /// Write the stdout of each child as one result terminated by a null character
+    ///
+    /// Output from each child is held until it exits, so results from different children aren't
+    /// mixed. Without --reuse every record gets its own child, and therefore its own result. This
+    /// lets results that contain newlines be passed intact to tools like `xargs -0` or `sort -z`.
+    #[clap(long, conflicts_with = "pty")]
+    print0: bool,
+
+    /// Run all children inside a new cgroup created at this path
+    ///
+    /// The path must be inside a cgroup v2 hierarchy that can be managed by the current user, e.g.
+    /// `/sys/fs/cgroup/xstream`. Limits set on the cgroup apply to all children together, and
+    /// their controllers must already be enabled in the parent's `cgroup.subtree_control`. The
+    /// cgroup is removed when xstream exits.
+    #[cfg(target_os = "linux")]
+    #[clap(long, value_name = "PATH")]
+    cgroup: Option<String>,
+
+    /// Limit the total memory of all children, e.g. 512M or 4G
+    #[cfg(target_os = "linux")]
+    #[clap(long, value_name = "SIZE", value_parser = parse_size, requires = "cgroup")]
+    memory_max: Option<u64>,
+
+    /// Limit the total cpu time of all children to this many cpus, e.g. 1.5
+    #[cfg(target_os = "linux")]
+    #[clap(long, value_name = "CPUS", value_parser = parse_cpus, requires = "cgroup")]
+    cpu_max: Option<f64>,
+
+    /// Restrict what children can do
+    ///
+    /// Filesystem restrictions use Landlock, and xstream fails if the kernel can't fully enforce
+    /// them unless --sandbox-best-effort is given. Network restrictions use seccomp to deny
+    /// creating any socket but a unix domain socket and using `io_uring`, and kill 32 bit children.
+    /// Where Landlock supports it (Linux 6.7 and later), binding and connecting TCP sockets is
+    /// denied too. Sockets inherited from xstream can still be used for other protocols, like
+    /// UDP, and on older kernels for TCP.
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    #[clap(long, value_enum, value_name = "PRESET")]
+    sandbox: Option<SandboxPreset>,
+
+    /// Allow sandboxed children to write beneath this directory
+    ///
+    /// Can be specified multiple times, defaults to the current directory.
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    #[clap(long, value_name = "DIR", requires = "sandbox")]
+    sandbox_dir: Vec<String>,
+
+    /// Run sandboxed children with only the filesystem restrictions the kernel supports
+    ///
+    /// On kernels with older versions of Landlock, or without it, children may be able to write
+    /// outside of the sandbox directories.
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    #[clap(long, requires = "sandbox")]
+    sandbox_best_effort: bool,
+
+    /// Use the options from a named profile in the config file
+    ///
+    /// Profiles are tables under `profiles` whose keys are long option names, e.g.
+    /// `[profiles.logs]` with `null = true` and `parallel = 8`. A profile can also specify a
+    /// `command` array that's used when no command is given. Arrays set options that can be
+    /// repeated, like `sandbox-dir = ["/a", "/b"]`. Options on the command line replace those in
+    /// the profile, and profile options that conflict with them are ignored. There's no option to
+    /// time out children, so profiles can't set one, but --cpu-limit bounds their cpu time.
+    #[clap(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// The config file to load profiles from
+    ///
+    /// Defaults to `$XDG_CONFIG_HOME/xstream/config.toml`.
+    #[clap(long, value_name = "PATH", requires = "profile")]
+    config: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    action: Option<Action>,
+
     /// The command to execute for each delimited stream
     ///
     /// It is often helpful to prefix this with "--" so that other arguments are not interpreted by
-    /// xstream
+    /// xstream, or so that it isn't mistaken for a subcommand like serve. This is required unless
+    /// it's specified by a profile.
     #[clap(value_parser = NonEmptyStringValueParser::new())]
-    command: String,
+    command: Option<String>,
 
     /// Any additional arguments to command
     #[clap(value_parser)]
     args: Vec<String>,
 }
 
-fn main() {
-    let args = Cli::parse();
+/// A reader that counts the bytes consumed from it
+struct Counted<R> {
+    inner: R,
+    consumed: u64,
+}
+
+impl<R: BufRead> Read for Counted<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(out)?;
+        self.consumed += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for Counted<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consumed += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+/// Where the streams to split come from
+#[derive(Debug)]
+enum Input {
+    /// A single stream from stdin
+    Stdin,
+    /// A single stream from the stdout of a shell command
+    Command(String),
+    /// The byte ranges of a seekable file, given as offsets and lengths
+    #[cfg(unix)]
+    Ranges(File, Vec<(u64, u64)>),
+    /// Every stream sent to a unix socket, until the supervisor stops it, and where to keep a
+    /// checkpoint
+    #[cfg(unix)]
+    Socket(UnixListener, Supervisor, Option<PathBuf>),
+    /// The records saved in an audit, and whether to send them to their original workers
+    Replay(Vec<Task>, bool),
+}
+
+/// How input is split into records and written to children
+#[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)]
+struct Splitter {
+    delim: String,
+    detect_delimiter: bool,
+    sniff_format: bool,
+    quoted: bool,
+    write_delim: Option<String>,
+    framing: Option<Framing>,
+    write_framing: Option<Framing>,
+    decoding: Option<Decoding>,
+    dedup: Option<Dedup>,
+    route: Option<Regex>,
+    route_records: bool,
+    #[cfg(feature = "encoding")]
+    encoding: Option<&'static encoding_rs::Encoding>,
+    tee: Option<File>,
+    #[cfg(unix)]
+    spool: Option<(PathBuf, u64)>,
+    manifest: Option<Manifest<BufWriter<File>>>,
+    audit: Option<Audit>,
+    skip_broken_pipe: bool,
+    dropped: u64,
+    close_stdin: bool,
+    stats: Option<Stats>,
+    spawn_record: Option<Rc<RefCell<Vec<u8>>>>,
+    #[cfg(feature = "otel")]
+    tracer: Option<BoxedTracer>,
+    dashboard: Option<Dashboard>,
+    checkpoint: Option<(PathBuf, Checkpoint)>,
+}
+
+impl Splitter {
+    /// Whether records need to be read into memory before they're written
+    fn buffered(&self) -> bool {
+        #[cfg(feature = "otel")]
+        if self.tracer.is_some() {
+            return true;
+        }
+        self.framing.is_some()
+            || self.write_framing.is_some()
+            || self.decoding.is_some()
+            || self.dedup.is_some()
+            || self.route.is_some()
+            || self.route_records
+            || self.manifest.is_some()
+            || self.audit.is_some()
+            || self.skip_broken_pipe
+            || self.close_stdin
+            || self.stats.is_some()
+            || self.spawn_record.is_some()
+            || self.dashboard.is_some()
+            || self.checkpoint.is_some()
+            || self.quoted
+    }
+
+    /// Split every stream from `input` among the processes of `pool`
+    ///
+    /// If stats are collected, they're printed to stderr afterwards.
+    fn split(&mut self, pool: &mut impl Pool, input: &Input) -> Result<(), Error> {
+        let res = match input {
+            Input::Stdin => self.run_stdin(pool),
+            Input::Command(producer) => self.run_command(pool, producer),
+            #[cfg(unix)]
+            Input::Ranges(file, ranges) => self.run_ranges(pool, file, ranges),
+            #[cfg(unix)]
+            Input::Socket(listener, supervisor, _) => self.serve(pool, listener, supervisor),
+            Input::Replay(tasks, _) => replay(pool, tasks, self.spawn_record.as_deref()),
+        };
+        if let Some(dashboard) = &mut self.dashboard {
+            match &res {
+                // children that failed were already counted when they were waited on
+                Ok(()) | Err(Error::NonZeroExitCode(_) | Error::KilledBySignal) => {}
+                Err(err) => dashboard.update(|progress| progress.error(err)),
+            }
+            dashboard.finish();
+        }
+        if let Some(stats) = &self.stats {
+            print_stats(stats);
+        }
+        if self.dropped > 0 {
+            eprintln!(
+                "xstream: dropped {} records that children stopped reading",
+                self.dropped
+            );
+        }
+        res
+    }
+
+    /// Prepare a raw input stream for splitting
+    ///
+    /// If the delimiter hasn't been detected yet, it's detected from the start of this stream.
+    fn reader<'a>(&mut self, input: impl BufRead + 'a) -> Result<Box<dyn BufRead + 'a>, Error> {
+        let mut input: Box<dyn BufRead + 'a> = Box::new(input);
+        if let Some(tee) = &self.tee {
+            input = Box::new(Tee::new(input, tee.try_clone().map_err(Error::Input)?));
+        }
+        #[cfg(feature = "encoding")]
+        if let Some(encoding) = self.encoding {
+            input = Box::new(Transcoder::new(input, encoding));
+        }
+        if self.detect_delimiter {
+            let sample = input.fill_buf().map_err(Error::Input)?;
+            if !sample.is_empty() {
+                if let Some(delim) = xstream_util::detect_delimiter(sample) {
+                    // all candidates are ascii
+                    str::from_utf8(delim).unwrap().clone_into(&mut self.delim);
+                }
+                eprintln!(
+                    "xstream: detected delimiter {}",
+                    self.delim.as_bytes().escape_ascii()
+                );
+                self.detect_delimiter = false;
+            }
+        }
+        if self.sniff_format {
+            let sample = input.fill_buf().map_err(Error::Input)?;
+            if !sample.is_empty() {
+                match xstream_util::sniff_format(sample) {
+                    Some((format, reason)) => {
+                        eprintln!("xstream: detected {format} because {reason}");
+                        self.set_format(format);
+                    }
+                    None => eprintln!(
+                        "xstream: couldn't detect the format, so records are delimited by {}",
+                        self.delim.as_bytes().escape_ascii()
+                    ),
+                }
+                self.sniff_format = false;
+            }
+        }
+        Ok(input)
+    }
+
+    /// Split input in a detected `format`
+    fn set_format(&mut self, format: Format) {
+        match format {
+            Format::Framed(framing) => self.framing = Some(framing),
+            Format::Csv(delim) | Format::Delimited(delim) => {
+                self.quoted = matches!(format, Format::Csv(_));
+                // all detected delimiters are ascii
+                str::from_utf8(delim).unwrap().clone_into(&mut self.delim);
+            }
+            Format::Jsonl => "\n".clone_into(&mut self.delim),
+            _ => {}
+        }
+    }
+
+    /// Split `input` among the processes of `pool`, and wait for them to finish
+    fn run(&mut self, pool: &mut impl Pool, input: impl BufRead) -> Result<(), Error> {
+        let mut input = self.reader(input)?;
+        if self.buffered() {
+            self.dispatch(pool, &mut input, false)?;
+            pool.join()
+        } else {
+            xstream_util::xstream(pool, &mut input, &self.delim, &self.write_delim)
+        }
+    }
+
+    /// Split stdin, through a spool if there is one
+    fn run_stdin(&mut self, pool: &mut impl Pool) -> Result<(), Error> {
+        #[cfg(unix)]
+        if let Some((dir, max_size)) = self.spool.clone() {
+            let spool = Spool::new(io::stdin(), &dir, max_size).map_err(Error::Input)?;
+            return self.run(pool, spool);
+        }
+        self.run(pool, io::stdin().lock())
+    }
+
+    /// Split the stdout of the shell command `producer`, which must also complete successfully
+    fn run_command(&mut self, pool: &mut impl Pool, producer: &str) -> Result<(), Error> {
+        #[cfg(unix)]
+        let mut command = Command::new("sh");
+        #[cfg(unix)]
+        command.arg("-c");
+        #[cfg(windows)]
+        let mut command = Command::new("cmd");
+        #[cfg(windows)]
+        command.arg("/C");
+        let mut child = command
+            .arg(producer)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(Error::Input)?;
+        let stdout = child.stdout.take().unwrap(); // piped above
+        #[cfg(unix)]
+        let res = match self.spool.clone() {
+            Some((dir, max_size)) => Spool::new(stdout, &dir, max_size)
+                .map_err(Error::Input)
+                .and_then(|spool| self.run(pool, spool)),
+            None => self.run(pool, BufReader::new(stdout)),
+        };
+        #[cfg(not(unix))]
+        let res = self.run(pool, BufReader::new(stdout));
+        if res.is_err() {
+            // nothing is reading its output anymore
+            let _ = child.kill();
+        }
+        let status = child.wait().map_err(Error::Input)?;
+        res?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::Input(io::Error::other(format!(
+                "input command failed: {status}"
+            ))))
+        }
+    }
+
+    /// Split the records in `ranges` of `file`
+    #[cfg(unix)]
+    fn run_ranges(
+        &mut self,
+        pool: &mut impl Pool,
+        mut file: &File,
+        ranges: &[(u64, u64)],
+    ) -> Result<(), Error> {
+        for &(offset, length) in ranges {
+            file.seek(SeekFrom::Start(offset)).map_err(Error::Input)?;
+            let mut input = self.reader(BufReader::new(file.take(length)))?;
+            self.dispatch(pool, &mut input, false)?;
+        }
+        pool.join()
+    }
+
+    /// Split every stream sent to `listener` among the processes of `pool`
+    ///
+    /// Once `supervisor` stops, the stream being split ends with an error, no more connections
+    /// are accepted, and this waits for the processes to finish. Otherwise this only returns if
+    /// accepting a connection fails, or writing to a process fails.
+    #[cfg(unix)]
+    fn serve(
+        &mut self,
+        pool: &mut impl Pool,
+        listener: &UnixListener,
+        supervisor: &Supervisor,
+    ) -> Result<(), Error> {
+        while supervisor.wait(listener).map_err(Error::Input)? {
+            let (stream, _) = listener.accept().map_err(Error::Input)?;
+            let conn = Connection { stream, supervisor };
+            let mut input = self.reader(BufReader::new(conn))?;
+            match self.dispatch(pool, &mut input, true) {
+                // a client disconnecting early shouldn't stop the server
+                Err(Error::Input(err)) => eprintln!("xstream: error reading stream: {err}"),
+                res => res?,
+            }
+            if let Some((path, checkpoint)) = &mut self.checkpoint {
+                checkpoint.routing = pool.routing();
+                save_checkpoint(path, checkpoint).map_err(Error::Checkpoint)?;
+            }
+        }
+        pool.join()
+    }
+
+    /// Note that `body`, from the `length` bytes of input at `offset`, was sent to `worker`
+    fn account(
+        &mut self,
+        offset: u64,
+        length: u64,
+        body: &[u8],
+        worker: usize,
+    ) -> Result<(), Error> {
+        if let Some(manifest) = &mut self.manifest {
+            manifest.record(offset, length, body, worker)?;
+        }
+        if let Some((_, checkpoint)) = &mut self.checkpoint {
+            checkpoint.record(length, worker);
+        }
+        if let Some(stats) = &mut self.stats {
+            stats.record(body.len());
+        }
+        Ok(())
+    }
+
+    /// Write every record in `input` to a process from `pool`
+    ///
+    /// If `terminate` is true, the delimiter is written after the last record even if it wasn't
+    /// in the input.
+    fn dispatch(
+        &mut self,
+        pool: &mut impl Pool,
+        input: &mut impl BufRead,
+        terminate: bool,
+    ) -> Result<(), Error> {
+        // NOTE this is copied so that self can be borrowed mutably while records are sent
+        let write_delim = match (&self.write_delim, self.framing) {
+            (Some(write_delim), _) => write_delim.as_bytes(),
+            (None, Some(_)) => b"",
+            (None, None) => self.delim.as_bytes(),
+        }
+        .to_vec();
+        let mut input = Counted {
+            inner: input,
+            consumed: 0,
+        };
+        let mut record = Vec::new();
+        let mut decoded = Vec::new();
+        let mut sent = Vec::new();
+        loop {
+            let offset = input.consumed;
+            let Some(hit_delim) = self.read(&mut input, &mut record)? else {
+                break;
+            };
+            let mut body = &record[..];
+            if let Some(dedup) = &mut self.dedup {
+                if dedup.is_duplicate(body) {
+                    continue;
+                }
+            }
+            if let Some(decoding) = self.decoding {
+                decoded.clear();
+                decoding.decode(body, &mut decoded)?;
+                body = &decoded;
+            }
+            // NOTE records are only traced once they're going to be dispatched
+            #[cfg(feature = "otel")]
+            let mut span = self.tracer.as_ref().map(|tracer| tracer.start("record"));
+            let key = self.key(body);
+            if let Some(record) = &self.spawn_record {
+                body.clone_into(&mut record.borrow_mut());
+            }
+            let mut proc = match key {
+                Some(key) => pool.get_key(key)?,
+                None => pool.get()?,
+            };
+            let end = (hit_delim || terminate).then_some(&write_delim[..]);
+            let mut res = self.send(proc, body, end);
+            if self.skip_broken_pipe && is_broken_pipe(&res) {
+                // the child stopped reading, so it's replaced and the record is sent again once
+                proc = pool.respawn()?;
+                res = self.send(proc, body, end);
+            }
+            let pid = proc.id();
+            match res {
+                Err(Error::Output(err))
+                    if self.skip_broken_pipe && err.kind() == io::ErrorKind::BrokenPipe =>
+                {
+                    self.dropped += 1;
+                    if let Some(dashboard) = &self.dashboard {
+                        let length = input.consumed - offset;
+                        dashboard.update(|progress| {
+                            progress.drop_record(length, &format!("pid {pid}: {err}"));
+                        });
+                    }
+                    #[cfg(feature = "otel")]
+                    if let Some(span) = &mut span {
+                        span.set_attributes(record_attributes(body, pid, pool.slot(), "dropped"));
+                        span.set_status(Status::error(err.to_string()));
+                    }
+                    // the record wasn't sent, so it's left out of the manifest
+                    continue;
+                }
+                res => res?,
+            }
+            let worker = pool.slot();
+            let length = input.consumed - offset;
+            if let Some(audit) = &mut self.audit {
+                sent.clear();
+                if self.spawn_record.is_some() {
+                    sent.extend_from_slice(body);
+                } else {
+                    write_record(&mut sent, body, self.write_framing, end)?;
+                }
+                audit.record(&sent, worker)?;
+            }
+            self.account(offset, length, body, worker)?;
+            if let Some(dashboard) = &self.dashboard {
+                dashboard.update(|progress| progress.record(length, body.len(), worker, pid));
+            }
+            #[cfg(feature = "otel")]
+            if let Some(span) = &mut span {
+                span.set_attributes(record_attributes(body, pid, worker, "sent"));
+                span.end();
+            }
+        }
+        match &mut self.manifest {
+            Some(manifest) => manifest.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Send `body` followed by `end` to `proc`
+    fn send(&self, proc: &mut Child, body: &[u8], end: Option<&[u8]>) -> Result<(), Error> {
+        if self.spawn_record.is_some() {
+            // the record was passed to the child when it was spawned
+            drop(proc.stdin.take());
+            Ok(())
+        } else {
+            let out = proc.stdin.as_mut().ok_or(Error::StdinNotPiped)?;
+            write_record(out, body, self.write_framing, end)?;
+            if self.close_stdin {
+                drop(proc.stdin.take());
+            }
+            Ok(())
+        }
+    }
+
+    /// The routing key of `body`, if it has one
+    fn key<'a>(&self, body: &'a [u8]) -> Option<&'a [u8]> {
+        if self.route_records {
+            Some(body)
+        } else {
+            self.route
+                .as_ref()
+                .and_then(|route| route.captures(body))
+                .and_then(|caps| caps.get(1))
+                .map(|key| key.as_bytes())
+        }
+    }
+
+    /// Replace `record` with the next record from `input`, without any delimiter
+    ///
+    /// Returns whether the record ended with a delimiter, or `None` at the end of the input.
+    fn read(&self, input: &mut impl BufRead, record: &mut Vec<u8>) -> Result<Option<bool>, Error> {
+        record.clear();
+        if let Some(framing) = self.framing {
+            return Ok(framing.read_frame(input, record)?.then_some(true));
+        }
+        let delim = self.delim.as_bytes();
+        let read = if self.quoted {
+            xstream_util::read_quoted_record(input, delim, record)?
+        } else {
+            xstream_util::read_record(input, delim, record)?
+        };
+        if read == 0 {
+            Ok(None)
+        } else if record.ends_with(delim) {
+            record.truncate(record.len() - delim.len());
+            Ok(Some(true))
+        } else {
+            Ok(Some(false))
+        }
+    }
+}
+
+/// The attributes of the span of `record`, sent to the child `pid` in `worker` with `outcome`
+#[cfg(feature = "otel")]
+fn record_attributes(
+    record: &[u8],
+    pid: u32,
+    worker: usize,
+    outcome: &'static str,
+) -> [KeyValue; 4] {
+    [
+        KeyValue::new(
+            "xstream.bytes",
+            i64::try_from(record.len()).unwrap_or(i64::MAX),
+        ),
+        KeyValue::new("process.pid", i64::from(pid)),
+        KeyValue::new("xstream.worker", i64::try_from(worker).unwrap_or(i64::MAX)),
+        KeyValue::new("xstream.outcome", outcome),
+    ]
+}
+
+/// Whether `res` failed because a child closed its stdin
+fn is_broken_pipe(res: &Result<(), Error>) -> bool {
+    matches!(res, Err(Error::Output(err)) if err.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// Write `body` to `out`, framed by `framing` or followed by `end`
+fn write_record(
+    out: &mut impl Write,
+    body: &[u8],
+    framing: Option<Framing>,
+    end: Option<&[u8]>,
+) -> Result<(), Error> {
+    if let Some(framing) = framing {
+        framing.write_frame(out, body)
+    } else {
+        out.write_all(body).map_err(Error::Output)?;
+        match end {
+            Some(end) => out.write_all(end).map_err(Error::Output),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Send every task in an audit to a process from `pool`, and wait for them to finish
+///
+/// If there's a `record` to pass to children when they're spawned, each task is put in it instead
+/// of being written to stdin.
+fn replay(
+    pool: &mut impl Pool,
+    tasks: &[Task],
+    record: Option<&RefCell<Vec<u8>>>,
+) -> Result<(), Error> {
+    for task in tasks {
+        match record {
+            Some(record) => {
+                *record.borrow_mut() = fs::read(&task.path).map_err(Error::Input)?;
+                drop(pool.get()?.stdin.take());
+            }
+            None => send_task(pool.get()?, task)?,
+        }
+    }
+    pool.join()
+}
+
+/// Send every task in an audit to the process of `pool` for its original worker
+fn replay_workers(pool: &mut Partitioned<impl Spawn>, tasks: &[Task]) -> Result<(), Error> {
+    for task in tasks {
+        send_task(pool.get_index(task.worker)?, task)?;
+    }
+    pool.join()
+}
+
+/// Write exactly what was saved for `task` to `proc`
+fn send_task(proc: &mut Child, task: &Task) -> Result<(), Error> {
+    let sent = fs::read(&task.path).map_err(Error::Input)?;
+    proc.stdin
+        .as_mut()
+        .ok_or(Error::StdinNotPiped)?
+        .write_all(&sent)
+        .map_err(Error::Output)
+}
+
+/// Print a one line summary of `stats` to stderr
+fn print_stats(stats: &Stats) {
+    let sizes = match stats.max() {
+        Some(max) => {
+            let [p50, p90, p99] = [50, 90, 99].map(|percent| stats.percentile(percent).unwrap());
+            format!(", record size p50 {p50} p90 {p90} p99 {p99} max {max}")
+        }
+        None => String::new(),
+    };
+    eprintln!(
+        "xstream: {} records, {} bytes{sizes}",
+        stats.records(),
+        stats.bytes()
+    );
+}
+
+/// Run the benchmarks described by `args` and print a table of the results
+fn bench(args: BenchArgs) -> Result<(), Error> {
+    let record_size = usize::try_from(args.record_size).expect("record size too large");
+    let mut data = Vec::with_capacity((record_size + 1) * args.records);
+    for _ in 0..args.records {
+        data.extend(std::iter::repeat_n(b'x', record_size));
+        data.push(b'\n');
+    }
+    let parallel = if args.parallel.is_empty() {
+        vec![thread::available_parallelism().map_or(1, Into::into)]
+    } else {
+        args.parallel
+    };
+    let pools = if args.pool.is_empty() {
+        BenchPool::value_variants().to_vec()
+    } else {
+        args.pool
+    };
+    let mut command_args = args.command.into_iter();
+    let mut command = Command::new(command_args.next().as_deref().unwrap_or("cat"));
+    command.args(command_args).stdout(Stdio::null());
+
+    #[allow(clippy::cast_precision_loss)]
+    let mib = data.len() as f64 / f64::from(1 << 20);
+    #[allow(clippy::cast_precision_loss)]
+    let records = args.records as f64;
+    println!(
+        "{:<12} {:>6} {:>10} {:>12} {:>10}",
+        "pool", "procs", "seconds", "records/s", "MiB/s"
+    );
+    for pool in pools {
+        for &procs in &parallel {
+            let elapsed = match pool {
+                BenchPool::Limiting => time_pool(&mut Limiting::new(&mut command, procs), &data),
+                BenchPool::Rotating => time_pool(&mut Rotating::new(&mut command, procs), &data),
+                BenchPool::Random => time_pool(&mut Random::new(&mut command, procs), &data),
+                BenchPool::Partitioned => {
+                    time_pool(&mut Partitioned::new(&mut command, procs), &data)
+                }
+            }?
+            .as_secs_f64();
+            println!(
+                "{:<12} {:>6} {:>10.3} {:>12.1} {:>10.1}",
+                pool.to_possible_value().unwrap().get_name(),
+                procs,
+                elapsed,
+                records / elapsed,
+                mib / elapsed,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Time how long it takes to split `data` among the processes of `pool`
+fn time_pool(pool: &mut impl Pool, data: &[u8]) -> Result<Duration, Error> {
+    let start = Instant::now();
+    xstream_util::xstream(pool, &mut &data[..], "\n", &None::<&[u8]>)?;
+    Ok(start.elapsed())
+}
+
+/// The arguments of `profile` that don't override or conflict with options on the command line
+///
+/// `raw_args` are the command line arguments, and `matches` are what they were parsed as.
+fn merge_profile(raw_args: &[OsString], matches: &ArgMatches, profile: Profile) -> Vec<String> {
+    let command = Cli::command();
+    let mut merged = Vec::new();
+    for (key, args) in profile.args {
+        let given = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()))
+            .is_some_and(|arg| {
+                matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            });
+        let conflicts = || {
+            let (program, rest) = raw_args.split_first().unwrap(); // always the program name
+            let res = Cli::command().try_get_matches_from(
+                iter::once(program.clone())
+                    .chain(args.iter().map(Into::into))
+                    .chain(rest.iter().cloned()),
+            );
+            matches!(res, Err(err) if err.kind() == ErrorKind::ArgumentConflict)
+        };
+        if !given && !conflicts() {
+            merged.extend(args);
+        }
+    }
+    merged
+}
+
+/// Parse command line arguments, applying any profile
+///
+/// The first argument is the name of the program.
+fn parse_cli(raw_args: Vec<OsString>) -> Cli {
+    // the profile may provide options the command line requires, so they're checked afterwards
+    let matches = Cli::command()
+        .ignore_errors(true)
+        .get_matches_from(&raw_args);
+    let args = if let Some(name) = matches.get_one::<String>("profile") {
+        let profile = matches
+            .get_one::<PathBuf>("config")
+            .cloned()
+            .or_else(default_config)
+            .ok_or_else(|| "couldn't find the config file".to_owned())
+            .and_then(|config| load_profile(&config, name))
+            .unwrap_or_else(|err| Cli::command().error(ErrorKind::InvalidValue, err).exit());
+        let command = profile.command.clone();
+        let profile_args = merge_profile(&raw_args, &matches, profile);
+        // reparse with the profile options first, leaving out any the command line replaces
+        let mut raw_args = raw_args.into_iter();
+        let mut args = Cli::parse_from(
+            raw_args
+                .next()
+                .into_iter()
+                .chain(profile_args.into_iter().map(Into::into))
+                .chain(raw_args),
+        );
+        if args.command.is_none() {
+            let mut command = command.into_iter();
+            args.command = command.next();
+            args.args = command.collect();
+        }
+        args
+    } else {
+        Cli::parse_from(raw_args)
+    };
+    if args.command.is_none() && args.action.is_none() {
+        Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "a command is required unless it's specified by a profile",
+            )
+            .exit();
+    }
+    if args.seed.is_some() && args.strategy != Strategy::Random {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--seed can only be used with --strategy random",
+            )
+            .exit();
+    }
+    if args.tui && !io::stderr().is_terminal() {
+        Cli::command()
+            .error(
+                ErrorKind::InvalidValue,
+                "--tui requires stderr to be a terminal",
+            )
+            .exit();
+    }
+    args
+}
+
+/// Listen on a unix socket at `path`, replacing it if it's left over from a previous server
+#[cfg(unix)]
+fn bind_socket(path: &Path) -> io::Result<UnixListener> {
+    match UnixListener::bind(path) {
+        Err(err)
+            if err.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(path).is_err() =>
+        {
+            fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        res => res,
+    }
+}
+
+/// What a server waits on besides its clients
+///
+/// The server stops on SIGTERM, and if systemd is watching it, the watchdog is pinged whenever
+/// the server makes progress or is idle waiting for input, so a server stuck writing to a child
+/// is restarted.
+#[cfg(unix)]
+#[derive(Debug)]
+struct Supervisor {
+    /// Readable once the server should stop, since nothing is ever read from it
+    stop: UnixStream,
+    #[cfg(target_os = "linux")]
+    watchdog: Option<(Arc<Notifier>, Duration)>,
+    #[cfg(target_os = "linux")]
+    pinged: Cell<Instant>,
+}
+
+#[cfg(unix)]
+impl Supervisor {
+    /// Stop on SIGTERM, and notify systemd if it's supervising this process
+    ///
+    /// This must be called before any other threads are started.
+    fn start() -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        let notifier = Notifier::from_env()?.map(Arc::new);
+        let (stop, mut stopping) = UnixStream::pair()?;
+        let mut signals = MaybeUninit::uninit();
+        // SAFETY: signals is initialized by sigemptyset before it's used
+        let signals = unsafe {
+            libc::sigemptyset(signals.as_mut_ptr());
+            let mut signals = signals.assume_init();
+            libc::sigaddset(&raw mut signals, libc::SIGTERM);
+            // only receive SIGTERM with sigwait, spawned children reset their signal mask
+            libc::pthread_sigmask(libc::SIG_BLOCK, &raw const signals, ptr::null_mut());
+            signals
+        };
+        #[cfg(target_os = "linux")]
+        let notify = notifier.clone();
+        thread::spawn(move || {
+            let mut signal = 0;
+            // SAFETY: both pointers are valid
+            unsafe { libc::sigwait(&raw const signals, &raw mut signal) };
+            let _ = stopping.write_all(b"x");
+            #[cfg(target_os = "linux")]
+            if let Some(notifier) = notify {
+                let _ = notifier.notify("STOPPING=1");
+            }
+        });
+
+        #[cfg(target_os = "linux")]
+        if let Some(notifier) = &notifier {
+            notifier.notify("READY=1")?;
+        }
+        Ok(Supervisor {
+            stop,
+            #[cfg(target_os = "linux")]
+            watchdog: notifier.and_then(|notifier| {
+                let interval = notifier.watchdog_interval()?;
+                Some((notifier, interval / 2))
+            }),
+            #[cfg(target_os = "linux")]
+            pinged: Cell::new(Instant::now()),
+        })
+    }
+
+    /// Wait until `input` can be read, or return false if the server should stop instead
+    fn wait(&self, input: &impl AsRawFd) -> io::Result<bool> {
+        loop {
+            let mut fds = [input.as_raw_fd(), self.stop.as_raw_fd()].map(|fd| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            });
+            // SAFETY: fds is valid for its length
+            let res = unsafe { libc::poll(fds.as_mut_ptr(), 2, self.timeout()) };
+            if res < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            self.ping();
+            if fds[1].revents != 0 {
+                return Ok(false);
+            } else if fds[0].revents != 0 {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// How long to wait in milliseconds before the watchdog needs to be pinged, or -1 for ever
+    fn timeout(&self) -> libc::c_int {
+        #[cfg(target_os = "linux")]
+        if let Some((_, interval)) = &self.watchdog {
+            let left = interval.saturating_sub(self.pinged.get().elapsed());
+            return libc::c_int::try_from(left.as_millis()).unwrap_or(libc::c_int::MAX);
+        }
+        -1
+    }
+
+    /// Ping the watchdog if it's due
+    fn ping(&self) {
+        #[cfg(target_os = "linux")]
+        if let Some((notifier, interval)) = &self.watchdog {
+            if self.pinged.get().elapsed() >= *interval {
+                let _ = notifier.notify("WATCHDOG=1");
+                self.pinged.set(Instant::now());
+            }
+        }
+    }
+}
+
+/// A stream sent to a server, that ends with an error once the server stops
+#[cfg(unix)]
+struct Connection<'a> {
+    stream: UnixStream,
+    supervisor: &'a Supervisor,
+}
+
+#[cfg(unix)]
+impl Read for Connection<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.supervisor.wait(&self.stream)? {
+            self.stream.read(buf)
+        } else {
+            Err(io::Error::other("stopped before the end of the stream"))
+        }
+    }
+}
+
+/// Create the splitter for the record options
+fn build_splitter(args: &mut Cli, input: &Input) -> Splitter {
     let delim = if args.null {
         "\0".to_owned()
     } else {
-        unescape_delimiter(args.delimiter)
+        unescape_delimiter(&args.delimiter)
+    };
+    #[cfg_attr(not(unix), allow(unused_mut))]
+    let mut splitter = Splitter {
+        delim,
+        detect_delimiter: args.detect_delimiter,
+        sniff_format: args.format == Some(InputFormat::Auto),
+        quoted: args.format == Some(InputFormat::Csv),
+        write_delim: args.write_delimiter.as_deref().map(unescape_delimiter),
+        framing: args.framing,
+        write_framing: args.write_framing,
+        decoding: args.decode,
+        dedup: args
+            .dedup_consecutive
+            .then(|| Dedup::new(args.dedup_window)),
+        route: args.route_regex.take(),
+        route_records: args.route.is_some(),
+        #[cfg(feature = "encoding")]
+        encoding: args.from_encoding,
+        tee: args
+            .tee_input
+            .as_ref()
+            .map(|path| File::create(path).unwrap()),
+        #[cfg(unix)]
+        spool: args.spool.take().map(|dir| (dir, args.spool_max)),
+        manifest: args
+            .manifest
+            .as_ref()
+            .map(|path| Manifest::new(BufWriter::new(File::create(path).unwrap())).unwrap()),
+        audit: args
+            .audit
+            .as_ref()
+            .map(|path| Audit::create(path, &audit_setup(args)).unwrap()),
+        skip_broken_pipe: args.on_broken_pipe == BrokenPipe::Skip,
+        dropped: 0,
+        #[cfg(unix)]
+        close_stdin: args.mutex.is_some(),
+        #[cfg(not(unix))]
+        close_stdin: false,
+        stats: args.stats.then(Stats::new),
+        spawn_record: None,
+        #[cfg(feature = "otel")]
+        tracer: args.otel.then(|| global::tracer("xstream")),
+        dashboard: args.tui.then(|| {
+            // other input can't be measured up front, or isn't the only source of records
+            Dashboard::start(matches!(input, Input::Stdin).then(stdin_len).flatten())
+        }),
+        checkpoint: match input {
+            #[cfg(unix)]
+            Input::Socket(_, _, Some(path)) => Some((path.clone(), load_checkpoint(path))),
+            _ => None,
+        },
+    };
+    #[cfg(unix)]
+    if args.record_env.is_some() {
+        splitter.spawn_record = Some(Rc::default());
+    }
+    #[cfg(target_os = "linux")]
+    if args.record_file {
+        splitter.spawn_record = Some(Rc::default());
+    }
+    if splitter.route.is_some() && args.parallel == 0 {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--route-regex requires a limited number of parallel processes",
+            )
+            .exit();
+    }
+    #[cfg(unix)]
+    if splitter.spool.is_some() && args.spool_max == 0 {
+        Cli::command()
+            .error(
+                ErrorKind::InvalidValue,
+                "--spool-max must be at least one byte",
+            )
+            .exit();
+    }
+    if splitter.route_records && args.parallel == 0 {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--route requires a limited number of parallel processes",
+            )
+            .exit();
+    }
+    splitter
+}
+
+/// The size of stdin if it's a regular file
+fn stdin_len() -> Option<u64> {
+    #[cfg(unix)]
+    {
+        let stdin = io::stdin().as_fd().try_clone_to_owned().ok()?;
+        let meta = File::from(stdin).metadata().ok()?;
+        meta.is_file().then_some(meta.len())
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+/// Create the command for children, running as the requested user and inside any sandbox
+fn build_command(args: &Cli) -> Command {
+    let program = args.command.as_deref().unwrap(); // checked while parsing
+    let mut command = Command::new(program);
+    #[cfg(target_os = "linux")]
+    let record_path = args.record_file.then(|| format!("/dev/fd/{RECORD_FD}"));
+    #[cfg(not(target_os = "linux"))]
+    let record_path: Option<String> = None;
+    match record_path {
+        Some(path) if args.args.iter().any(|arg| arg.contains("{}")) => {
+            command.args(args.args.iter().map(|arg| arg.replace("{}", &path)));
+        }
+        Some(path) => {
+            command.args(&args.args).arg(path);
+        }
+        None => {
+            command.args(&args.args);
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(gid) = args.group.or(args.user.and_then(|user| user.gid)) {
+            command.gid(gid);
+        }
+        if let Some(user) = args.user {
+            command.uid(user.uid);
+        }
+        if let Some(secs) = args.cpu_limit {
+            let limit = libc::rlimit {
+                rlim_cur: secs,
+                rlim_max: secs.saturating_add(1),
+            };
+            // SAFETY: setrlimit is async signal safe
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::setrlimit(libc::RLIMIT_CPU, &raw const limit) < 0 {
+                        Err(io::Error::last_os_error())
+                    } else {
+                        Ok(())
+                    }
+                });
+            }
+        }
+    }
+
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    if let Some(preset) = args.sandbox {
+        let mut sandbox = Sandbox::new();
+        if args.sandbox_best_effort {
+            sandbox.best_effort();
+        }
+        if preset != SandboxPreset::ReadOnly {
+            sandbox.deny_network();
+        }
+        if preset != SandboxPreset::NoNetwork {
+            if args.sandbox_dir.is_empty() {
+                sandbox.read_only([env::current_dir().unwrap()]);
+            } else {
+                sandbox.read_only(&args.sandbox_dir);
+            }
+        }
+        sandbox.attach(&mut command).unwrap();
+    }
+    command
+}
+
+/// Wrap the command in the spawners requested on the command line
+fn build_spawner(command: Command, args: &mut Cli) -> Box<dyn Spawn> {
+    #[cfg(unix)]
+    let mut spawner: Box<dyn Spawn> = match args.joblog.take() {
+        Some(path) => {
+            let log = LineWriter::new(File::create(path).unwrap());
+            Box::new(JobLog::new(command, log).unwrap())
+        }
+        None => Box::new(command),
+    };
+    #[cfg(not(unix))]
+    let mut spawner: Box<dyn Spawn> = Box::new(command);
+    #[cfg(unix)]
+    if let Some(name) = args.mutex.take() {
+        spawner = Box::new(if name.contains('/') {
+            Locked::new(spawner, name).unwrap()
+        } else {
+            Locked::named(spawner, &name).unwrap()
+        });
+    }
+    if args.init_stdin.is_some() || args.final_stdin.is_some() {
+        spawner = Box::new(
+            Payloads::new(spawner)
+                .init(args.init_stdin.take().unwrap_or_default().0)
+                .finish(args.final_stdin.take().unwrap_or_default().0),
+        );
+    }
+    #[cfg(unix)]
+    if args.pty {
+        spawner = Box::new(Pty::new(spawner));
+    }
+    if args.prefix_stderr {
+        spawner = Box::new(Prefixed::new(spawner, io::stderr().is_terminal()));
+    }
+    if args.print0 {
+        spawner = Box::new(Collected::new(spawner, b'\0'));
+    }
+    if let Some(path) = args.pid_file.take() {
+        spawner = Box::new(PidFile::create(spawner, path).unwrap());
+    }
+    #[cfg(feature = "otel")]
+    if args.otel {
+        spawner = Box::new(Traced::new(spawner, global::tracer("xstream")));
+    }
+    spawner
+}
+
+/// Parse the options of a subcommand that splits its own input, as if they were passed to xstream
+///
+/// If the first argument isn't an option, the `--` before the command was consumed while parsing
+/// the subcommand, so it's added back so the command's arguments aren't parsed as options.
+fn parse_action_args(action: &str, mut raw_args: Vec<OsString>) -> Cli {
+    if raw_args
+        .first()
+        .is_some_and(|arg| !arg.to_string_lossy().starts_with('-'))
+    {
+        raw_args.insert(0, "--".into());
+    }
+    let args = parse_cli(env::args_os().take(1).chain(raw_args).collect());
+    if args.action.is_some() || args.input_cmd.is_some() || args.rerun_partition.is_some() {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "{action} can't be combined with another subcommand, --input-cmd, or \
+                     --rerun-partition"
+                ),
+            )
+            .exit();
+    }
+    args
+}
+
+/// How records are sent to children, to save in an audit
+fn audit_setup(args: &Cli) -> Setup {
+    #[cfg_attr(not(unix), allow(unused_mut))]
+    let mut delivery = Delivery::Stdin;
+    #[cfg(unix)]
+    if let Some(name) = &args.record_env {
+        delivery = Delivery::Env(name.clone());
+    }
+    #[cfg(target_os = "linux")]
+    if args.record_file {
+        delivery = Delivery::File;
+    }
+    Setup {
+        init: args.init_stdin.clone().unwrap_or_default().0,
+        finish: args.final_stdin.clone().unwrap_or_default().0,
+        delivery,
+    }
+}
+
+/// Send records like the audit with `setup` did, unless payloads are given again in `args`
+fn restore_setup(args: &mut Cli, setup: Setup, same_workers: bool) {
+    if args.init_stdin.is_none() && !setup.init.is_empty() {
+        args.init_stdin = Some(Payload(setup.init));
+    }
+    if args.final_stdin.is_none() && !setup.finish.is_empty() {
+        args.final_stdin = Some(Payload(setup.finish));
+    }
+    let err = match setup.delivery {
+        Delivery::Stdin => return,
+        #[cfg(unix)]
+        Delivery::Env(name) => {
+            args.record_env = Some(name);
+            None
+        }
+        #[cfg(target_os = "linux")]
+        Delivery::File => {
+            args.record_file = true;
+            None
+        }
+        _ => Some("the records of this audit can't be passed to children on this platform"),
+    };
+    let err = err.or_else(|| {
+        (args.reuse || same_workers).then_some(
+            "this audit passed every record to its own child, so it can't be replayed with \
+             --reuse or --same-workers",
+        )
+    });
+    if let Some(err) = err {
+        Cli::command()
+            .error(ErrorKind::ArgumentConflict, err)
+            .exit();
+    }
+}
+
+/// Find the input to split for `action` and the options in `args`
+///
+/// Subcommands replace `args` with the options they were passed.
+fn select_input(args: &mut Cli, action: Option<Action>) -> Input {
+    let mut input = Input::Stdin;
+    match action {
+        #[cfg(unix)]
+        Some(Action::Serve(serve_args)) => {
+            *args = parse_action_args("serve", serve_args.args);
+            let listener = bind_socket(&serve_args.socket).unwrap();
+            input = Input::Socket(
+                listener,
+                Supervisor::start().unwrap(),
+                serve_args.checkpoint,
+            );
+        }
+        Some(Action::Replay(replay_args)) => {
+            *args = parse_action_args("replay", replay_args.args);
+            let tasks = read_audit(&replay_args.dir).unwrap();
+            let setup = read_setup(&replay_args.dir).unwrap();
+            restore_setup(args, setup, replay_args.same_workers);
+            if replay_args.same_workers {
+                let workers = tasks.iter().map(|task| task.worker + 1).max().unwrap_or(1);
+                if args.parallel < workers {
+                    Cli::command()
+                        .error(
+                            ErrorKind::ArgumentConflict,
+                            format!("--same-workers needs --parallel of at least {workers} to replay this audit"),
+                        )
+                        .exit();
+                }
+            }
+            input = Input::Replay(tasks, replay_args.same_workers);
+        }
+        _ => {}
+    }
+    if let Some(producer) = args.input_cmd.take() {
+        input = Input::Command(producer);
+    }
+    #[cfg(unix)]
+    if let Some(worker) = args.rerun_partition {
+        let manifest = args.manifest.take().unwrap(); // required while parsing
+        let ranges = File::open(manifest)
+            .and_then(|file| read_manifest(BufReader::new(file)))
+            .unwrap()
+            .into_iter()
+            .filter(|entry| entry.worker == worker)
+            .map(|entry| (entry.offset, entry.length))
+            .collect();
+        let stdin = io::stdin().as_fd().try_clone_to_owned().unwrap();
+        input = Input::Ranges(File::from(stdin), ranges);
+    }
+    input
+}
+
+/// The provider of exported spans, which flushes any that are left when it's dropped
+#[cfg(feature = "otel")]
+#[derive(Debug)]
+struct Tracing(SdkTracerProvider);
+
+#[cfg(feature = "otel")]
+impl Drop for Tracing {
+    fn drop(&mut self) {
+        // NOTE this also runs while panicking, so spans of failed runs are still exported
+        if let Err(err) = self.0.shutdown() {
+            eprintln!("xstream: couldn't export spans: {err}");
+        }
+    }
+}
+
+/// Read the checkpoint at `path`, or start a new one if it doesn't exist
+#[cfg(unix)]
+fn load_checkpoint(path: &Path) -> Checkpoint {
+    let res = match fs::read_to_string(path) {
+        Ok(contents) => toml::from_str(&contents).map_err(|err| err.to_string()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Checkpoint::new()),
+        Err(err) => Err(err.to_string()),
+    };
+    res.unwrap_or_else(|err| {
+        Cli::command()
+            .error(
+                ErrorKind::InvalidValue,
+                format!("couldn't read checkpoint {}: {err}", path.display()),
+            )
+            .exit()
+    })
+}
+
+/// Replace the checkpoint at `path`, so it's never left partially written
+#[cfg(unix)]
+fn save_checkpoint(path: &Path, checkpoint: &Checkpoint) -> io::Result<()> {
+    let contents = toml::to_string(checkpoint).map_err(io::Error::other)?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(tmp, path)
+}
+
+/// Create a pool, continuing from the routing state of a checkpoint if there is one
+fn restore<P>(
+    routing: Option<Routing>,
+    spawner: Box<dyn Spawn>,
+    new: impl FnOnce(Box<dyn Spawn>) -> P,
+    with_routing: impl FnOnce(Box<dyn Spawn>, Routing) -> Option<P>,
+) -> P {
+    match routing {
+        None => new(spawner),
+        Some(routing) => with_routing(spawner, routing).unwrap_or_else(|| {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "the checkpoint was saved by a different kind of pool",
+                )
+                .exit()
+        }),
+    }
+}
+
+/// Export spans with OTLP over HTTP, configured by the environment
+#[cfg(feature = "otel")]
+fn init_tracing() -> Tracing {
+    let exporter = SpanExporter::builder().with_http().build().unwrap();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("xstream").build())
+        .build();
+    global::set_tracer_provider(provider.clone());
+    Tracing(provider)
+}
+
+fn main() {
+    let mut args = parse_cli(env::args_os().collect());
+    let input = match args.action.take() {
+        Some(Action::Bench(bench_args)) => {
+            bench(bench_args).unwrap();
+            return;
+        }
+        action => select_input(&mut args, action),
     };
-    let write_delim = args.write_delimiter.map(unescape_delimiter);
+    #[cfg(feature = "otel")]
+    let _tracing = args.otel.then(init_tracing);
+    let mut splitter = build_splitter(&mut args, &input);
+
+    let mut command = build_command(&args);
 
-    let mut command = Command::new(args.command);
-    command.args(args.args);
-    if args.reuse {
-        let mut pool = Rotating::new(command, args.parallel);
-        xstream_util::xstream(&mut pool, &mut io::stdin().lock(), &delim, &write_delim).unwrap();
+    #[cfg(target_os = "linux")]
+    let _cgroup = args.cgroup.take().map(|path| {
+        let cgroup = Cgroup::create(path).unwrap();
+        if let Some(bytes) = args.memory_max {
+            cgroup.set_memory_max(bytes).unwrap();
+        }
+        if let Some(cpus) = args.cpu_max {
+            cgroup.set_cpu_max(cpus).unwrap();
+        }
+        cgroup.attach(&mut command);
+        cgroup
+    });
+
+    #[cfg_attr(not(unix), allow(unused_mut))]
+    let mut spawner = build_spawner(command, &mut args);
+    #[cfg(unix)]
+    if let (Some(name), Some(record)) = (args.record_env.take(), &splitter.spawn_record) {
+        spawner = Box::new(RecordEnv::new(
+            spawner,
+            name,
+            args.record_env_max,
+            Rc::clone(record),
+        ));
+    }
+    #[cfg(target_os = "linux")]
+    if let (true, Some(record)) = (args.record_file, &splitter.spawn_record) {
+        spawner = Box::new(RecordFile::new(spawner, Rc::clone(record)));
+    }
+
+    if let Some(dashboard) = &splitter.dashboard {
+        spawner = Box::new(dashboard.watch(spawner));
+    }
+
+    let routing = splitter
+        .checkpoint
+        .as_ref()
+        .and_then(|(_, checkpoint)| checkpoint.routing);
+    if let Input::Replay(tasks, true) = &input {
+        replay_workers(&mut Partitioned::new(spawner, args.parallel), tasks).unwrap();
+    } else if splitter.route.is_some() {
+        let new = |spawner| Partitioned::new(spawner, args.parallel);
+        let mut pool = restore(routing, spawner, new, Partitioned::with_routing);
+        splitter.split(&mut pool, &input).unwrap();
+    } else if let Some(route) = args.route.take() {
+        let new = |spawner| Routed::new(spawner, args.parallel, route.clone());
+        let with_routing = |spawner, routing| Routed::with_routing(spawner, route.clone(), routing);
+        let mut pool = restore(routing, spawner, new, with_routing);
+        splitter.split(&mut pool, &input).unwrap();
+    } else if args.reuse && args.strategy == Strategy::Random {
+        let new = |spawner| match args.seed {
+            Some(seed) => Random::with_seed(spawner, args.parallel, seed),
+            None => Random::new(spawner, args.parallel),
+        };
+        let mut pool = restore(routing, spawner, new, Random::with_routing);
+        splitter.split(&mut pool, &input).unwrap();
+    } else if args.reuse {
+        let new = |spawner| Rotating::new(spawner, args.parallel);
+        let mut pool = restore(routing, spawner, new, Rotating::with_routing);
+        splitter.split(&mut pool, &input).unwrap();
     } else {
-        let mut pool = Limiting::new(command, args.parallel);
-        xstream_util::xstream(&mut pool, &mut io::stdin().lock(), &delim, &write_delim).unwrap();
+        let new = |spawner| Limiting::new(spawner, args.parallel);
+        let mut pool = restore(routing, spawner, new, |_, _| None);
+        splitter.split(&mut pool, &input).unwrap();
     }
 }
 
@@ -148,12 +2376,236 @@ mod escape_tests {
 }
 
 #[cfg(test)]
-mod cli_tests {
-    use super::Cli;
+mod payload_tests {
+    use super::{parse_payload, Payload};
+
+    #[test]
+    fn parse_string_payload() {
+        assert_eq!(parse_payload(r"HELLO\n"), Ok(Payload(b"HELLO\n".to_vec())));
+    }
+
+    #[test]
+    fn parse_missing_file_payload() {
+        assert!(parse_payload("@/does/not/exist").is_err());
+    }
+}
+
+#[cfg(test)]
+mod size_tests {
+    use super::parse_size;
+
+    #[test]
+    fn parse_plain_size() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+    }
+
+    #[test]
+    fn parse_suffixed_size() {
+        assert_eq!(parse_size("4k"), Ok(4096));
+        assert_eq!(parse_size("2M"), Ok(2 << 20));
+        assert_eq!(parse_size("1GiB"), Ok(1 << 30));
+    }
+
+    #[test]
+    fn parse_invalid_size() {
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+}
+
+#[cfg(test)]
+mod profile_tests {
+    use super::{merge_profile, parse_profile, Cli, Profile};
     use clap::CommandFactory;
+    use std::ffi::OsString;
+
+    #[test]
+    fn parse_full_profile() {
+        let table = r#"
+            null = true
+            reuse = false
+            parallel = 8
+            delimiter = "-"
+            sandbox-dir = ["/a", "/b"]
+            command = ["wc", "-l"]
+        "#
+        .parse()
+        .unwrap();
+        let expected = Profile {
+            args: vec![
+                ("delimiter".into(), vec!["--delimiter=-".into()]),
+                ("null".into(), vec!["--null".into()]),
+                ("parallel".into(), vec!["--parallel=8".into()]),
+                ("reuse".into(), vec![]),
+                (
+                    "sandbox-dir".into(),
+                    vec!["--sandbox-dir=/a".into(), "--sandbox-dir=/b".into()],
+                ),
+            ],
+            command: vec!["wc".into(), "-l".into()],
+        };
+        assert_eq!(parse_profile(&table), Ok(expected));
+    }
+
+    #[test]
+    fn merge_with_command_line() {
+        let profile = parse_profile(&"null = true\nparallel = 8\nreuse = true".parse().unwrap());
+        let raw_args: Vec<OsString> = ["xstream", "-d", ",", "-p", "2", "cat"]
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        let matches = Cli::command().get_matches_from(&raw_args);
+        assert_eq!(
+            merge_profile(&raw_args, &matches, profile.unwrap()),
+            ["--reuse"]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_profile() {
+        assert!(parse_profile(&"command = 5".parse().unwrap()).is_err());
+        assert!(parse_profile(&"parallel = { a = 1 }".parse().unwrap()).is_err());
+    }
+}
+
+#[cfg(test)]
+mod route_tests {
+    use super::parse_route;
+
+    #[test]
+    fn parse_route_with_group() {
+        let route = parse_route(r"id=(\d+)").unwrap();
+        let caps = route.captures(b"x id=42 y").unwrap();
+        assert_eq!(caps.get(1).unwrap().as_bytes(), b"42");
+    }
+
+    #[test]
+    fn parse_route_without_group() {
+        assert!(parse_route(r"id=\d+").is_err());
+        assert!(parse_route(r"(").is_err());
+    }
+}
+
+#[cfg(all(test, feature = "encoding"))]
+mod encoding_tests {
+    use super::parse_encoding;
+
+    #[test]
+    fn parse_known_encoding() {
+        assert_eq!(parse_encoding("latin1"), Ok(encoding_rs::WINDOWS_1252));
+        assert_eq!(parse_encoding("UTF-16LE"), Ok(encoding_rs::UTF_16LE));
+    }
+
+    #[test]
+    fn parse_unknown_encoding() {
+        assert!(parse_encoding("klingon").is_err());
+    }
+}
+
+#[cfg(all(test, unix))]
+mod user_tests {
+    use super::{parse_group, parse_user, User};
+
+    #[test]
+    fn parse_root_user() {
+        let root = User {
+            uid: 0,
+            gid: Some(0),
+        };
+        assert_eq!(parse_user("root"), Ok(root));
+        assert_eq!(parse_user("0"), Ok(root));
+    }
+
+    #[test]
+    fn parse_unknown_numeric_user() {
+        assert_eq!(
+            parse_user("987654"),
+            Ok(User {
+                uid: 987_654,
+                gid: None
+            })
+        );
+    }
+
+    #[test]
+    fn parse_unknown_user() {
+        assert!(parse_user("not a user").is_err());
+    }
+
+    #[test]
+    fn parse_numeric_group() {
+        assert_eq!(parse_group("0"), Ok(0));
+    }
+
+    #[test]
+    fn parse_unknown_group() {
+        assert!(parse_group("not a group").is_err());
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::{Action, Cli, Payload};
+    use clap::{CommandFactory, Parser};
 
     #[test]
     fn test_cli() {
         Cli::command().debug_assert()
     }
+
+    #[test]
+    fn test_payload_args() {
+        let args =
+            Cli::try_parse_from(["xstream", "--init-stdin", "a", "--final-stdin", "b", "cat"])
+                .unwrap();
+        assert_eq!(args.init_stdin, Some(Payload(b"a".to_vec())));
+        assert_eq!(args.final_stdin, Some(Payload(b"b".to_vec())));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mutex_conflicts_with_final_stdin() {
+        use clap::error::ErrorKind;
+
+        let err = Cli::try_parse_from(["xstream", "--mutex", "a", "--final-stdin", "b", "cat"])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+        assert!(
+            Cli::try_parse_from(["xstream", "--mutex", "a", "--init-stdin", "b", "cat"]).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_bench_subcommand() {
+        let args = Cli::try_parse_from(["xstream", "bench", "-p", "2", "-p", "4"]).unwrap();
+        assert!(matches!(args.action, Some(Action::Bench(bench)) if bench.parallel == [2, 4]));
+        let args = Cli::try_parse_from(["xstream", "--", "bench"]).unwrap();
+        assert!(args.action.is_none());
+        assert_eq!(args.command.as_deref(), Some("bench"));
+        let args = Cli::try_parse_from(["xstream", "-p", "2", "bench"]).unwrap();
+        assert_eq!(args.command.as_deref(), Some("bench"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_subcommand() {
+        let args =
+            Cli::try_parse_from(["xstream", "serve", "--socket", "s", "-r", "--", "cat"]).unwrap();
+        let Some(Action::Serve(serve)) = args.action else {
+            panic!("not parsed as serve");
+        };
+        assert_eq!(serve.args, ["-r", "--", "cat"]);
+    }
+
+    #[test]
+    fn test_replay_subcommand() {
+        let args = Cli::try_parse_from(["xstream", "replay", "--same-workers", "dir", "--", "cat"])
+            .unwrap();
+        let Some(Action::Replay(replay)) = args.action else {
+            panic!("not parsed as replay");
+        };
+        assert!(replay.same_workers);
+        assert_eq!(replay.dir.as_os_str(), "dir");
+        assert_eq!(replay.args, ["cat"]);
+    }
 }
@@ -0,0 +1,154 @@
+//! Manifests of dispatched records
+//!
+//! When records are split among many processes, it's hard to verify afterwards that none were lost
+//! or corrupted. A manifest records where every record came from in the input, which process it
+//! was sent to, and a checksum of what was sent.
+use super::pool::Error;
+use std::io::{self, BufRead, ErrorKind, Write};
+use xxhash_rust::xxh64::xxh64;
+
+/// The columns of the manifest
+const HEADER: &str = "seq\toffset\tlength\txxh64\tworker\n";
+
+/// A line of a manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// The sequence number of the record
+    pub seq: u64,
+    /// The offset of the record in the input
+    pub offset: u64,
+    /// The number of bytes the record was read from, including any delimiter or framing
+    pub length: u64,
+    /// The XXH64 checksum of the record sent
+    pub checksum: u64,
+    /// The worker the record was sent to
+    pub worker: usize,
+}
+
+impl Entry {
+    /// Parse a line of a manifest, without the trailing newline
+    #[must_use]
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let entry = Entry {
+            seq: fields.next()?.parse().ok()?,
+            offset: fields.next()?.parse().ok()?,
+            length: fields.next()?.parse().ok()?,
+            checksum: u64::from_str_radix(fields.next()?, 16).ok()?,
+            worker: fields.next()?.parse().ok()?,
+        };
+        fields.next().is_none().then_some(entry)
+    }
+}
+
+/// A writer of a manifest of every record sent to a process
+///
+/// The manifest is tab separated with a header line, and has a line for every record with its
+/// sequence number, the offset and length of the bytes it was read from in the input, the
+/// XXH64 checksum of the record sent, and the worker it was sent to. Workers are numbered by the
+/// slot of the pool they ran in.
+#[derive(Debug)]
+pub struct Manifest<W> {
+    out: W,
+    seq: u64,
+}
+
+impl<W: Write> Manifest<W> {
+    /// Create a new manifest written to `out`, starting with the header
+    ///
+    /// # Errors
+    ///
+    /// If the header can't be written.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(HEADER.as_bytes())?;
+        Ok(Manifest { out, seq: 0 })
+    }
+
+    /// Add `record`, read from `length` bytes at `offset` in the input, and sent to `worker`
+    ///
+    /// # Errors
+    ///
+    /// If the line can't be written.
+    pub fn record(
+        &mut self,
+        offset: u64,
+        length: u64,
+        record: &[u8],
+        worker: usize,
+    ) -> Result<(), Error> {
+        writeln!(
+            self.out,
+            "{}\t{offset}\t{length}\t{:016x}\t{worker}",
+            self.seq,
+            xxh64(record, 0),
+        )
+        .map_err(Error::Manifest)?;
+        self.seq += 1;
+        Ok(())
+    }
+
+    /// Flush any buffered lines
+    ///
+    /// # Errors
+    ///
+    /// If the lines can't be written.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.out.flush().map_err(Error::Manifest)
+    }
+}
+
+/// Read every entry of a manifest written by a previous run
+///
+/// # Errors
+///
+/// If there are problems reading from `input`, or it isn't a valid manifest.
+pub fn read_manifest(input: impl BufRead) -> io::Result<Vec<Entry>> {
+    let mut lines = input.lines();
+    if lines.next().transpose()?.as_deref() != Some(HEADER.trim_end()) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "missing manifest header",
+        ));
+    }
+    lines
+        .map(|line| {
+            let line = line?;
+            Entry::parse(&line).ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid manifest line: {line}"),
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_manifest, xxh64, Entry, Manifest};
+
+    #[test]
+    fn round_trip() {
+        let mut manifest = Manifest::new(Vec::new()).unwrap();
+        manifest.record(0, 2, b"a", 1).unwrap();
+        manifest.record(2, 3, b"bc", 0).unwrap();
+        let out = manifest.out;
+        let entries = read_manifest(&out[..]).unwrap();
+        let expected = Entry {
+            seq: 1,
+            offset: 2,
+            length: 3,
+            checksum: xxh64(b"bc", 0),
+            worker: 0,
+        };
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1], expected);
+    }
+
+    #[test]
+    fn invalid_manifest() {
+        assert!(read_manifest(&b"0\t0\t1\t0\t0\n"[..]).is_err());
+        assert_eq!(Entry::parse("0\t0\t1\tx\t0"), None);
+        assert_eq!(Entry::parse("0\t0\t1\t0\t0\t0"), None);
+    }
+}
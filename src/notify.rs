@@ -0,0 +1,69 @@
+//! Service manager notifications
+//!
+//! systemd can supervise long running services that report when they're ready and periodically
+//! confirm they're still alive. This sends those notifications using the `sd_notify` protocol,
+//! without depending on libsystemd.
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::time::Duration;
+
+/// A connection to the service manager that started this process
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+}
+
+impl Notifier {
+    /// Connect to the socket in `NOTIFY_SOCKET`, if this was started by a service manager
+    ///
+    /// # Errors
+    ///
+    /// If the socket address is invalid, or a socket can't be created.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+        // names starting with @ are in the abstract namespace
+        let addr = match path.as_bytes().strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(&path)?,
+        };
+        Ok(Some(Notifier {
+            socket: UnixDatagram::unbound()?,
+            addr,
+        }))
+    }
+
+    /// Send `state` to the service manager, e.g. `READY=1` or `WATCHDOG=1`
+    ///
+    /// # Errors
+    ///
+    /// If the notification can't be sent.
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        self.socket
+            .send_to_addr(state.as_bytes(), &self.addr)
+            .map(|_| ())
+    }
+
+    /// How often the service manager expects `WATCHDOG=1`, if it's watching this process
+    #[must_use]
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        if let Some(pid) = env::var_os("WATCHDOG_PID") {
+            if pid != OsStr::new(&process::id().to_string()) {
+                return None;
+            }
+        }
+        env::var("WATCHDOG_USEC")
+            .ok()?
+            .parse()
+            .ok()
+            .filter(|&usec| usec > 0)
+            .map(Duration::from_micros)
+    }
+}
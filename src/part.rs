@@ -0,0 +1,225 @@
+//! Partitioned process pool
+//!
+//! This pool has a fixed number of processes, and sends every record with the same key to the
+//! same process. This is useful when each process needs to see every record for a group, e.g. to
+//! aggregate them. To effectively manage cleanup, this needs to be dropped, so panics while using
+//! this may result in zombie processes.
+use super::checkpoint::Routing;
+use super::pool::{Error, Pool, Spawn};
+use std::process::Child;
+
+/// Hash a key with 64 bit FNV-1a
+///
+/// Unlike the hashers in std, this is stable, so keys are assigned to the same process across
+/// runs.
+pub(crate) fn hash_key(key: &[u8]) -> u64 {
+    key.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// The index of the process out of `num_procs` that gets records with `key`
+pub(crate) fn partition(key: &[u8], num_procs: usize) -> usize {
+    // NOTE the remainder is less than the number of processes so it fits in a usize
+    #[allow(clippy::cast_possible_truncation)]
+    let ind = (hash_key(key) % num_procs as u64) as usize;
+    ind
+}
+
+/// A pool that assigns records to processes by key
+///
+/// Processes are spawned lazily the first time a key is assigned to them. Records without a key
+/// are sent to processes in a round-robin order.
+#[derive(Debug)]
+pub struct Partitioned<S> {
+    procs: Vec<Option<Child>>,
+    spawner: S,
+    ind: usize,
+    slot: usize,
+}
+
+impl<S: Spawn> Partitioned<S> {
+    /// Create a new empty pool with `num_procs` partitions
+    ///
+    /// `spawner` is usually a `Command`.
+    ///
+    /// # Panics
+    ///
+    /// If `num_procs` is zero.
+    pub fn new(spawner: S, num_procs: usize) -> Self {
+        assert_ne!(num_procs, 0, "partitioned pools need at least one process");
+        Partitioned {
+            procs: (0..num_procs).map(|_| None).collect(),
+            spawner,
+            ind: 0,
+            slot: 0,
+        }
+    }
+
+    /// Create a pool of processes that were already spawned, with a partition for every child
+    ///
+    /// Like `Rotating::with_children`, `spawner` is used to wait for `children`, and to spawn a
+    /// replacement when one is respawned. Every child must have a piped stdin.
+    ///
+    /// # Panics
+    ///
+    /// If `children` is empty.
+    pub fn with_children(spawner: S, children: impl IntoIterator<Item = Child>) -> Self {
+        let procs: Vec<_> = children.into_iter().map(Some).collect();
+        assert!(!procs.is_empty(), "a pool needs at least one child");
+        Partitioned {
+            procs,
+            spawner,
+            ind: 0,
+            slot: 0,
+        }
+    }
+
+    /// Create a new empty pool that continues routing from a checkpoint
+    ///
+    /// Keys are assigned to the same partitions as before. Returns `None` if `routing` isn't for a
+    /// `Partitioned` pool.
+    pub fn with_routing(spawner: S, routing: Routing) -> Option<Self> {
+        match routing {
+            Routing::Partitioned { procs, next } if next < procs => {
+                let mut pool = Self::new(spawner, procs);
+                pool.ind = next;
+                Some(pool)
+            }
+            _ => None,
+        }
+    }
+
+    /// The number of partitions
+    pub(crate) fn num_procs(&self) -> usize {
+        self.procs.len()
+    }
+
+    /// The index of the process that gets records with `key`
+    #[must_use]
+    pub fn partition(&self, key: &[u8]) -> usize {
+        partition(key, self.procs.len())
+    }
+
+    /// Get the process at `ind`, spawning it if necessary
+    ///
+    /// # Errors
+    ///
+    /// If the process needs to be spawned and can't be.
+    ///
+    /// # Panics
+    ///
+    /// If `ind` isn't less than the number of processes.
+    pub fn get_index(&mut self, ind: usize) -> Result<&mut Child, Error> {
+        self.slot = ind;
+        let slot = &mut self.procs[ind];
+        if slot.is_none() {
+            *slot = Some(self.spawner.spawn(ind)?);
+        }
+        Ok(slot.as_mut().unwrap()) // just spawned
+    }
+}
+
+impl<S: Spawn> Pool for Partitioned<S> {
+    /// Get the next process in round-robin order
+    fn get(&mut self) -> Result<&mut Child, Error> {
+        let ind = self.ind;
+        self.ind = (ind + 1) % self.procs.len();
+        self.get_index(ind)
+    }
+
+    /// Get the process responsible for `key`
+    fn get_key(&mut self, key: &[u8]) -> Result<&mut Child, Error> {
+        self.get_index(self.partition(key))
+    }
+
+    /// The partition of the process most recently returned
+    fn slot(&self) -> usize {
+        self.slot
+    }
+
+    /// Replace the process most recently returned
+    fn respawn(&mut self) -> Result<&mut Child, Error> {
+        let proc = self.spawner.spawn(self.slot)?;
+        if let Some(old) = self.procs[self.slot].replace(proc) {
+            self.spawner.wait(old)?;
+        }
+        Ok(self.procs[self.slot].as_mut().unwrap()) // just replaced
+    }
+
+    fn routing(&self) -> Option<Routing> {
+        Some(Routing::Partitioned {
+            procs: self.procs.len(),
+            next: self.ind,
+        })
+    }
+
+    /// Wait for all processes to finish successfully
+    ///
+    /// Errors will terminate early and not wait for reamining processes to finish. To continue
+    /// waiting for them anyway you can continue to call join until you get a success, this will
+    /// indicate that there are no more running processes under management by the pool.
+    fn join(&mut self) -> Result<(), Error> {
+        // NOTE we take each process out one at a time so that errors don't drop the rest of our
+        // processes creating zombies
+        for slot in &mut self.procs {
+            if let Some(proc) = slot.take() {
+                self.spawner.wait(proc)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S> Drop for Partitioned<S> {
+    fn drop(&mut self) {
+        // kill any children left in self
+        for proc in self.procs.iter_mut().flatten() {
+            let _ = proc.kill();
+        }
+        // wait for them to be cleaned up
+        for proc in self.procs.iter_mut().flatten() {
+            let _ = proc.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_key, Partitioned};
+    use crate::{Pool, Routing};
+    use std::process::{Child, Command, Stdio};
+
+    #[test]
+    fn stable_hash() {
+        assert_eq!(hash_key(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash_key(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn restore_routing() {
+        let routing = Routing::Partitioned { procs: 3, next: 2 };
+        let pool = Partitioned::with_routing(Command::new("true"), routing).unwrap();
+        assert_eq!(pool.routing(), Some(routing));
+        assert_eq!(
+            pool.partition(b"a"),
+            Partitioned::new(Command::new("true"), 3).partition(b"a")
+        );
+        let rotating = Routing::Rotating { procs: 3, next: 2 };
+        assert!(Partitioned::with_routing(Command::new("true"), rotating).is_none());
+    }
+
+    #[test]
+    fn adopts_children() {
+        let mut cmd = Command::new("cat");
+        cmd.stdin(Stdio::piped()).stdout(Stdio::null());
+        let children: Vec<_> = (0..2).map(|_| cmd.spawn().unwrap()).collect();
+        let pids: Vec<_> = children.iter().map(Child::id).collect();
+        let mut pool = Partitioned::with_children(cmd, children);
+        assert_eq!(pool.get_index(1).unwrap().id(), pids[1]);
+        let respawned = pool.respawn().unwrap().id();
+        assert!(!pids.contains(&respawned));
+        assert_eq!(pool.get_index(0).unwrap().id(), pids[0]);
+        pool.join().unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//! Fixed payloads for child processes
+//!
+//! Some programs expect a handshake, like a header or configuration, before they start reading
+//! records, or a command to persist their results before their input ends. This writes fixed
+//! payloads to every child, independent of the records it's sent.
+use super::pool::{Error, Spawn};
+use std::io::Write;
+use std::process::{Child, Command};
+
+/// A spawner that writes fixed payloads to every child
+#[derive(Debug)]
+pub struct Payloads<S> {
+    inner: S,
+    init: Vec<u8>,
+    finish: Vec<u8>,
+}
+
+impl<S: Spawn> Payloads<S> {
+    /// Wrap `inner` without any payloads
+    pub fn new(inner: S) -> Self {
+        Payloads {
+            inner,
+            init: Vec::new(),
+            finish: Vec::new(),
+        }
+    }
+
+    /// Write `payload` to the stdin of every child immediately after it's spawned
+    #[must_use]
+    pub fn init(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.init = payload.into();
+        self
+    }
+
+    /// Write `payload` to the stdin of every child right before it's closed
+    #[must_use]
+    pub fn finish(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.finish = payload.into();
+        self
+    }
+}
+
+impl<S: Spawn> Spawn for Payloads<S> {
+    fn command(&mut self) -> &mut Command {
+        self.inner.command()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        let mut child = self.inner.spawn(slot)?;
+        if !self.init.is_empty() {
+            let res = match child.stdin.as_mut() {
+                Some(stdin) => stdin.write_all(&self.init).map_err(Error::Output),
+                None => Err(Error::StdinNotPiped),
+            };
+            if let Err(err) = res {
+                // the child isn't managed by a pool yet, so we need to clean it up
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(err);
+            }
+        }
+        Ok(child)
+    }
+
+    fn wait(&mut self, mut child: Child) -> Result<(), Error> {
+        let res = if self.finish.is_empty() {
+            Ok(())
+        } else {
+            match child.stdin.as_mut() {
+                Some(stdin) => stdin.write_all(&self.finish).map_err(Error::Output),
+                None => Err(Error::StdinNotPiped),
+            }
+        };
+        // NOTE we always wait so the child is cleaned up, and its failure is the likely cause
+        self.inner.wait(child).and(res)
+    }
+}
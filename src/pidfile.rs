@@ -0,0 +1,91 @@
+//! Pid files for monitoring child processes
+//!
+//! External tools may need to monitor or signal individual children, but they can't easily tell
+//! which processes belong to xstream. This keeps a file with the pid of this process and every
+//! live child up to date as children are spawned and exit.
+use super::pool::{Error, Spawn};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command};
+
+/// A spawner that records the pids of live children in a file
+///
+/// The first line of the file is the pid of this process, and every following line is the pid of
+/// a live child. The file is replaced atomically whenever it changes, and removed when this is
+/// dropped.
+#[derive(Debug)]
+pub struct PidFile<S> {
+    inner: S,
+    path: PathBuf,
+    pids: BTreeSet<u32>,
+}
+
+impl<S: Spawn> PidFile<S> {
+    /// Wrap `inner` and write the pid of this process to a new file at `path`
+    ///
+    /// # Errors
+    ///
+    /// If the file can't be written.
+    pub fn create(inner: S, path: impl Into<PathBuf>) -> io::Result<Self> {
+        let pid_file = PidFile {
+            inner,
+            path: path.into(),
+            pids: BTreeSet::new(),
+        };
+        pid_file.write()?;
+        Ok(pid_file)
+    }
+
+    /// The path of the pid file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replace the file with the current pids
+    fn write(&self) -> io::Result<()> {
+        let mut contents = Vec::new();
+        for pid in iter::once(process::id()).chain(self.pids.iter().copied()) {
+            writeln!(contents, "{pid}")?;
+        }
+        // write to a temporary file first so readers never see a partial file
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(tmp, &self.path)
+    }
+}
+
+impl<S: Spawn> Spawn for PidFile<S> {
+    fn command(&mut self) -> &mut Command {
+        self.inner.command()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        let mut child = self.inner.spawn(slot)?;
+        self.pids.insert(child.id());
+        if let Err(err) = self.write() {
+            // the child isn't managed by a pool yet, so we need to clean it up
+            self.pids.remove(&child.id());
+            let _ = child.kill();
+            let _ = self.inner.wait(child);
+            return Err(Error::PidFile(err));
+        }
+        Ok(child)
+    }
+
+    fn wait(&mut self, child: Child) -> Result<(), Error> {
+        let pid = child.id();
+        let res = self.inner.wait(child);
+        self.pids.remove(&pid);
+        res.and(self.write().map_err(Error::PidFile))
+    }
+}
+
+impl<S> Drop for PidFile<S> {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
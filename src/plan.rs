@@ -0,0 +1,298 @@
+//! Planning how input would be split without spawning anything
+//!
+//! This mirrors how every pool chooses a process for each record, so partitioning strategies can
+//! be tested or visualized against real input without running any commands.
+use super::part::partition;
+use super::pool::Error;
+use super::random::random_index;
+use std::fmt::{self, Debug, Formatter};
+use std::io::BufRead;
+
+/// A description of a pool to plan with
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolSpec {
+    /// A `Limiting` pool with at most this many processes at once, or unbounded if zero, which
+    /// sends every record to a new process
+    Limiting(usize),
+    /// A `Rotating` pool with this many processes, or unbounded if zero
+    Rotating(usize),
+    /// A `Random` pool with this many processes, or unbounded if zero, and this seed
+    Random(usize, u64),
+    /// A `Partitioned` pool with this many processes
+    Partitioned(usize),
+}
+
+/// The process a single record would be sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Assignment {
+    /// The offset of the record in the input
+    pub offset: u64,
+    /// The length of the record in the input, including its delimiter
+    pub length: u64,
+    /// The slot of the process the record is sent to
+    ///
+    /// This is the same as `Pool::slot`, so it matches the worker in manifests and audits.
+    pub worker: usize,
+}
+
+/// A function that extracts the key of a record
+type KeyFn<'a> = Box<dyn FnMut(&[u8]) -> Option<Vec<u8>> + 'a>;
+
+/// An iterator over the planned assignment of every record in the input
+///
+/// This is created by [`plan`].
+pub struct Plan<'a, R> {
+    input: R,
+    delim: Vec<u8>,
+    pool: PoolSpec,
+    key: Option<KeyFn<'a>>,
+    record: Vec<u8>,
+    offset: u64,
+    spawned: usize,
+    ind: usize,
+    state: u64,
+}
+
+/// Plan how `input` delimited by `delim` would be split among the processes of `pool`
+///
+/// Records are read lazily as the plan is iterated.
+///
+/// # Panics
+///
+/// If `pool` is `Partitioned` with zero processes.
+pub fn plan<'a, R: BufRead>(input: R, delim: impl AsRef<[u8]>, pool: PoolSpec) -> Plan<'a, R> {
+    assert_ne!(
+        pool,
+        PoolSpec::Partitioned(0),
+        "partitioned pools need at least one process"
+    );
+    let state = match pool {
+        PoolSpec::Random(_, seed) => seed,
+        _ => 0,
+    };
+    Plan {
+        input,
+        delim: delim.as_ref().to_vec(),
+        pool,
+        key: None,
+        record: Vec::new(),
+        offset: 0,
+        spawned: 0,
+        ind: 0,
+        state,
+    }
+}
+
+impl<'a, R: BufRead> Plan<'a, R> {
+    /// Route records by the key that `key` returns for them, like `Pool::get_key`
+    ///
+    /// `key` is called with each record without its delimiter. Records without a key, and pools
+    /// that don't route by key, are planned as usual.
+    #[must_use]
+    pub fn key(mut self, key: impl FnMut(&[u8]) -> Option<Vec<u8>> + 'a) -> Self {
+        self.key = Some(Box::new(key));
+        self
+    }
+
+    /// Choose the process for the next record, like the pool would
+    fn worker(&mut self, key: Option<&[u8]>) -> usize {
+        match (self.pool, key) {
+            (PoolSpec::Partitioned(procs), Some(key)) => partition(key, procs),
+            (PoolSpec::Rotating(procs) | PoolSpec::Partitioned(procs), _) if procs > 0 => {
+                let ind = self.ind;
+                self.ind = (ind + 1) % procs;
+                ind
+            }
+            (PoolSpec::Random(procs, _), _) if procs > 0 && self.spawned == procs => {
+                random_index(&mut self.state, procs)
+            }
+            (PoolSpec::Limiting(procs), _) if procs > 0 => {
+                self.spawned += 1;
+                (self.spawned - 1) % procs
+            }
+            _ => {
+                self.spawned += 1;
+                self.spawned - 1
+            }
+        }
+    }
+}
+
+impl<R> Debug for Plan<'_, R> {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        fmt.debug_struct("Plan")
+            .field("pool", &self.pool)
+            .field("offset", &self.offset)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R: BufRead> Iterator for Plan<'_, R> {
+    type Item = Result<Assignment, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.record.clear();
+        let length = match super::read_record(&mut self.input, &self.delim, &mut self.record) {
+            Ok(0) => return None,
+            Ok(length) => length as u64,
+            Err(err) => return Some(Err(err)),
+        };
+        let body = self
+            .record
+            .strip_suffix(&self.delim[..])
+            .unwrap_or(&self.record);
+        let key = self.key.as_mut().and_then(|key| key(body));
+        let worker = self.worker(key.as_deref());
+        let offset = self.offset;
+        self.offset += length;
+        Some(Ok(Assignment {
+            offset,
+            length,
+            worker,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plan, Assignment, PoolSpec};
+    use crate::part::partition;
+    #[cfg(unix)]
+    use crate::{read_manifest, Limiting, Manifest, Partitioned, Pool, Random, Rotating};
+    #[cfg(unix)]
+    use std::process::Command;
+
+    fn workers(input: &str, pool: PoolSpec) -> Vec<usize> {
+        plan(input.as_bytes(), "\n", pool)
+            .map(|res| res.unwrap().worker)
+            .collect()
+    }
+
+    #[test]
+    fn offsets() {
+        let planned: Vec<_> = plan(&b"a\nbc\nd"[..], "\n", PoolSpec::Limiting(0))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            planned,
+            [
+                Assignment {
+                    offset: 0,
+                    length: 2,
+                    worker: 0
+                },
+                Assignment {
+                    offset: 2,
+                    length: 3,
+                    worker: 1
+                },
+                Assignment {
+                    offset: 5,
+                    length: 1,
+                    worker: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn limiting() {
+        assert_eq!(
+            workers("a\nb\nc\nd\ne\n", PoolSpec::Limiting(2)),
+            [0, 1, 0, 1, 0]
+        );
+    }
+
+    #[test]
+    fn rotating() {
+        assert_eq!(
+            workers("a\nb\nc\nd\ne\n", PoolSpec::Rotating(2)),
+            [0, 1, 0, 1, 0]
+        );
+        assert_eq!(workers("a\nb\nc\n", PoolSpec::Rotating(0)), [0, 1, 2]);
+    }
+
+    #[test]
+    fn random() {
+        let input = "a\n".repeat(100);
+        let first = workers(&input, PoolSpec::Random(3, 7));
+        assert_eq!(first[..3], [0, 1, 2]);
+        assert!(first.iter().all(|&worker| worker < 3));
+        assert_eq!(first, workers(&input, PoolSpec::Random(3, 7)));
+    }
+
+    #[test]
+    fn partitioned() {
+        let planned: Vec<_> = plan(&b"a 1\nb 2\na 3\nc\n"[..], "\n", PoolSpec::Partitioned(4))
+            .key(|record| {
+                record
+                    .split(|&b| b == b' ')
+                    .nth(1)
+                    .map(|_| record[..1].to_vec())
+            })
+            .map(|res| res.unwrap().worker)
+            .collect();
+        assert_eq!(
+            planned,
+            [
+                partition(b"a", 4),
+                partition(b"b", 4),
+                partition(b"a", 4),
+                0
+            ]
+        );
+    }
+
+    /// Record where a real pool sends every record of `input` in a manifest
+    #[cfg(unix)]
+    fn manifest(input: &str, mut pool: impl Pool) -> Vec<usize> {
+        let mut out = Vec::new();
+        let mut manifest = Manifest::new(&mut out).unwrap();
+        let mut offset = 0;
+        for record in input.split_inclusive('\n') {
+            let body = record.strip_suffix('\n').unwrap_or(record).as_bytes();
+            pool.get_key(&body[..1]).unwrap();
+            manifest
+                .record(offset, record.len() as u64, body, pool.slot())
+                .unwrap();
+            offset += record.len() as u64;
+        }
+        pool.join().unwrap();
+        read_manifest(&out[..])
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.worker)
+            .collect()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn matches_manifest() {
+        let input = "a\nb\nc\na\nd\nb\ne\n".repeat(3);
+        let key = |record: &[u8]| Some(record[..1].to_vec());
+        let planned = |pool| -> Vec<_> {
+            plan(input.as_bytes(), "\n", pool)
+                .key(key)
+                .map(|res| res.unwrap().worker)
+                .collect()
+        };
+        let cmd = || Command::new("true");
+        assert_eq!(
+            planned(PoolSpec::Limiting(3)),
+            manifest(&input, Limiting::new(cmd(), 3))
+        );
+        assert_eq!(
+            planned(PoolSpec::Rotating(3)),
+            manifest(&input, Rotating::new(cmd(), 3))
+        );
+        assert_eq!(
+            planned(PoolSpec::Random(3, 7)),
+            manifest(&input, Random::with_seed(cmd(), 3, 7))
+        );
+        assert_eq!(
+            planned(PoolSpec::Partitioned(3)),
+            manifest(&input, Partitioned::new(cmd(), 3))
+        );
+    }
+}
@@ -1,10 +1,11 @@
 //! A trait for a generic process pool used by xstream
+use super::checkpoint::Routing;
 use std::borrow::BorrowMut;
 use std::error;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::io;
-use std::process::Child;
+use std::process::{Child, Command, Stdio};
 
 /// Internal function to wait for a process
 ///
@@ -37,6 +38,22 @@ pub enum Error {
     Spawn(io::Error),
     /// An error occured while trying to wait for a child process
     Wait(io::Error),
+    /// An error occured while trying to relay the output of a child process
+    Relay(io::Error),
+    /// A record couldn't be decoded
+    Decode,
+    /// The input wasn't framed correctly, or a record was too long to frame
+    Frame,
+    /// An error occured while trying to update a pid file
+    PidFile(io::Error),
+    /// An error occured while trying to write to a job log
+    JobLog(io::Error),
+    /// An error occured while trying to write to a manifest
+    Manifest(io::Error),
+    /// An error occured while trying to write a copy of a record to an audit
+    Audit(io::Error),
+    /// An error occured while trying to save a checkpoint
+    Checkpoint(io::Error),
 }
 
 impl Display for Error {
@@ -47,6 +64,56 @@ impl Display for Error {
 
 impl error::Error for Error {}
 
+/// A type that can spawn and wait on the child processes of a pool
+///
+/// This is implemented for anything that can be borrowed as a `Command`, but can also be
+/// implemented to customize how the children of a pool are created or cleaned up.
+pub trait Spawn {
+    /// The command used to spawn new children
+    fn command(&mut self) -> &mut Command;
+
+    /// Spawn a new child process with a piped stdin to fill `slot` in a pool
+    ///
+    /// # Errors
+    ///
+    /// When anything goes wrong when trying to create a new process.
+    fn spawn(&mut self, _slot: usize) -> Result<Child, Error> {
+        self.command()
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(Error::Spawn)
+    }
+
+    /// Wait for a child process created by `spawn` to complete successfully
+    ///
+    /// # Errors
+    ///
+    /// When anything goes wrong when waiting for the process, including non-zero exit codes.
+    fn wait(&mut self, child: Child) -> Result<(), Error> {
+        wait_proc(child)
+    }
+}
+
+impl<C: BorrowMut<Command>> Spawn for C {
+    fn command(&mut self) -> &mut Command {
+        self.borrow_mut()
+    }
+}
+
+impl Spawn for Box<dyn Spawn> {
+    fn command(&mut self) -> &mut Command {
+        (**self).command()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        (**self).spawn(slot)
+    }
+
+    fn wait(&mut self, child: Child) -> Result<(), Error> {
+        (**self).wait(child)
+    }
+}
+
 /// A type that can `get` child processes on demand
 pub trait Pool {
     /// Fetch a process from the pool
@@ -59,6 +126,41 @@ pub trait Pool {
     /// When anything goes wrong when trying to create a new process.
     fn get(&mut self) -> Result<&mut Child, Error>;
 
+    /// Fetch a process from the pool for a record with a routing `key`
+    ///
+    /// Pools that don't route records by key ignore it, which is the default.
+    ///
+    /// # Errors
+    ///
+    /// When anything goes wrong when trying to create a new process.
+    fn get_key(&mut self, key: &[u8]) -> Result<&mut Child, Error> {
+        let _ = key;
+        self.get()
+    }
+
+    /// The slot of the process most recently fetched from the pool
+    ///
+    /// Slots number the processes a pool can run at once from zero. A process that replaces one
+    /// that exited takes its slot, so running processes never share a slot.
+    fn slot(&self) -> usize;
+
+    /// Replace the process most recently fetched from the pool with a new one in the same slot
+    ///
+    /// This is for processes that stopped reading their input early. The old process is waited
+    /// on, so this fails if it didn't exit successfully.
+    ///
+    /// # Errors
+    ///
+    /// When anything goes wrong when waiting for the old process or spawning the new one.
+    fn respawn(&mut self) -> Result<&mut Child, Error>;
+
+    /// The routing state of the pool, to save in a checkpoint
+    ///
+    /// Pools that choose processes without any state have none, which is the default.
+    fn routing(&self) -> Option<Routing> {
+        None
+    }
+
     /// Wait for all spawned processes to complete successfully
     ///
     /// # Errors
@@ -0,0 +1,116 @@
+//! Prefixed stderr for child processes
+//!
+//! When many children write diagnostics at once their lines are interleaved, and it's impossible
+//! to tell which child wrote what. This captures the stderr of every child, and relays it to the
+//! stderr of this process a line at a time, with each line prefixed by the worker that wrote it.
+use super::pool::{Error, Spawn};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::panic;
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// The ansi colors cycled through for each worker, skipping black and white
+const COLORS: [u8; 6] = [31, 32, 33, 34, 35, 36];
+
+/// A spawner that prefixes every line a child writes to stderr with a `[worker-N]` tag
+///
+/// Workers are numbered by the slot of the child in its pool, like in manifests and audits.
+#[derive(Debug)]
+pub struct Prefixed<S> {
+    inner: S,
+    color: bool,
+    relays: HashMap<u32, JoinHandle<io::Result<()>>>,
+}
+
+impl<S: Spawn> Prefixed<S> {
+    /// Wrap `inner` so that the stderr of children it spawns is prefixed
+    ///
+    /// If `color` is true, the tag of each worker is colored with ansi escape codes. Any stderr
+    /// configured on the command of `inner` will be replaced.
+    pub fn new(mut inner: S, color: bool) -> Self {
+        inner.command().stderr(Stdio::piped());
+        Prefixed {
+            inner,
+            color,
+            relays: HashMap::new(),
+        }
+    }
+}
+
+impl<S: Spawn> Spawn for Prefixed<S> {
+    fn command(&mut self) -> &mut Command {
+        self.inner.command()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        let mut child = self.inner.spawn(slot)?;
+        let prefix = tag(slot, self.color);
+        if let Some(stderr) = child.stderr.take() {
+            self.relays.insert(
+                child.id(),
+                thread::spawn(move || relay(stderr, &prefix, io::stderr())),
+            );
+        }
+        Ok(child)
+    }
+
+    fn wait(&mut self, child: Child) -> Result<(), Error> {
+        let relay = self.relays.remove(&child.id());
+        let res = self.inner.wait(child);
+        if let Some(relay) = relay {
+            relay
+                .join()
+                .unwrap_or_else(|err| panic::resume_unwind(err))
+                .map_err(Error::Relay)?;
+        }
+        res
+    }
+}
+
+/// The tag written before every line of a worker
+fn tag(worker: usize, color: bool) -> Vec<u8> {
+    if color {
+        let color = COLORS[worker % COLORS.len()];
+        format!("\x1b[{color}m[worker-{worker}]\x1b[0m ").into_bytes()
+    } else {
+        format!("[worker-{worker}] ").into_bytes()
+    }
+}
+
+/// Copy every line of `input` to `out` after `prefix`
+///
+/// Each line is written at once so lines from different workers aren't mixed. A final line
+/// without a newline gets one added.
+fn relay(input: impl Read, prefix: &[u8], mut out: impl Write) -> io::Result<()> {
+    let mut input = BufReader::new(input);
+    let mut line = prefix.to_vec();
+    loop {
+        line.truncate(prefix.len());
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        if line.last() != Some(&b'\n') {
+            line.push(b'\n');
+        }
+        out.write_all(&line)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{relay, tag};
+
+    #[test]
+    fn prefixes_lines() {
+        let mut out = Vec::new();
+        relay(&b"a\n\nb"[..], &tag(2, false), &mut out).unwrap();
+        assert_eq!(out, b"[worker-2] a\n[worker-2] \n[worker-2] b\n");
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(tag(0, true), b"\x1b[31m[worker-0]\x1b[0m ");
+        assert_eq!(tag(7, true), b"\x1b[32m[worker-7]\x1b[0m ");
+    }
+}
@@ -0,0 +1,316 @@
+//! A live dashboard of progress
+//!
+//! Long runs give no indication of how far along they are, or whether one worker is falling
+//! behind the others. This keeps counts of what's been read and sent, and how children exited,
+//! and redraws them in place on stderr until the run finishes.
+use super::pool::{Error, Spawn};
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::process::{Child, Command};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The number of workers shown on the dashboard
+const WORKERS: usize = 16;
+
+/// The number of errors shown on the dashboard
+const ERRORS: usize = 5;
+
+/// The width of the throughput bars on the dashboard
+const BAR: usize = 30;
+
+/// Progress shown on the dashboard
+#[derive(Debug)]
+pub struct Progress {
+    start: Instant,
+    input_len: Option<u64>,
+    consumed: u64,
+    sent: u64,
+    dropped: u64,
+    failed: u64,
+    /// The latest pid, and the bytes and records sent to each worker, indexed by its slot
+    workers: Vec<(u32, u64, u64)>,
+    errors: VecDeque<String>,
+}
+
+impl Progress {
+    /// Start tracking the progress through input of `input_len` bytes, if it's known
+    #[must_use]
+    pub fn new(input_len: Option<u64>) -> Self {
+        Progress {
+            start: Instant::now(),
+            input_len,
+            consumed: 0,
+            sent: 0,
+            dropped: 0,
+            failed: 0,
+            workers: Vec::new(),
+            errors: VecDeque::with_capacity(ERRORS),
+        }
+    }
+
+    /// Count a record of `length` bytes of input, of which `sent` were sent to the child `pid`
+    ///
+    /// Workers are numbered by the `slot` of the child in its pool, like in manifests and audits.
+    pub fn record(&mut self, length: u64, sent: usize, slot: usize, pid: u32) {
+        self.consumed += length;
+        self.sent += 1;
+        if self.workers.len() <= slot {
+            self.workers.resize(slot + 1, (0, 0, 0));
+        }
+        let worker = &mut self.workers[slot];
+        worker.0 = pid;
+        worker.1 += sent as u64;
+        worker.2 += 1;
+    }
+
+    /// Count a record of `length` bytes of input that wasn't sent because of `err`
+    pub fn drop_record(&mut self, length: u64, err: &impl Display) {
+        self.consumed += length;
+        self.dropped += 1;
+        self.error(err);
+    }
+
+    /// Count a child that exited unsuccessfully because of `err`
+    pub fn fail(&mut self, err: &impl Display) {
+        self.failed += 1;
+        self.error(err);
+    }
+
+    /// Add `err` to the recent errors
+    pub fn error(&mut self, err: &impl Display) {
+        if self.errors.len() == ERRORS {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(err.to_string());
+    }
+
+    /// Render the progress after `elapsed` as lines of text
+    #[must_use]
+    pub fn render(&self, elapsed: Duration) -> Vec<String> {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let input = match self.input_len {
+            Some(len) if len > 0 => {
+                #[allow(clippy::cast_precision_loss)]
+                let percent = self.consumed as f64 * 100.0 / len as f64;
+                format!("{percent:5.1}% of {} read", format_bytes(len))
+            }
+            _ => format!("{} read", format_bytes(self.consumed)),
+        };
+        let mut lines = vec![
+            format!("xstream  {secs:.1}s  {input}"),
+            format!(
+                "records  {} sent  {} dropped  children  {} failed",
+                self.sent, self.dropped, self.failed
+            ),
+        ];
+        let shown = &self.workers[..self.workers.len().min(WORKERS)];
+        let most = shown.iter().map(|worker| worker.1).max().unwrap_or(0);
+        for (slot, &(pid, bytes, records)) in shown.iter().enumerate() {
+            let filled = usize::try_from(bytes * BAR as u64 / most.max(1)).unwrap_or(0);
+            #[allow(
+                clippy::cast_precision_loss,
+                clippy::cast_possible_truncation,
+                clippy::cast_sign_loss
+            )]
+            let rate = (bytes as f64 / secs) as u64;
+            lines.push(format!(
+                "worker {slot:<4} {:<8} [{}{}] {:>10} {:>10}/s {records:>8} records",
+                pid,
+                "#".repeat(filled),
+                " ".repeat(BAR - filled),
+                format_bytes(bytes),
+                format_bytes(rate),
+            ));
+        }
+        let hidden = self.workers.len() - shown.len();
+        if hidden > 0 {
+            lines.push(format!("... and {hidden} more workers"));
+        }
+        if !self.errors.is_empty() {
+            lines.push("recent errors:".to_owned());
+            lines.extend(self.errors.iter().map(|err| format!("  {err}")));
+        }
+        lines
+    }
+}
+
+/// Format a number of bytes with a binary prefix
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    #[allow(clippy::cast_precision_loss)]
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// A dashboard of progress, redrawn on stderr in place until it's finished
+#[derive(Debug)]
+pub struct Dashboard {
+    progress: Arc<Mutex<Progress>>,
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Dashboard {
+    /// Start redrawing a dashboard for input of `input_len` bytes, if it's known
+    ///
+    /// The percentage of the input read is only shown when the length is known, so it should only
+    /// be given when every record comes from input of that length.
+    #[must_use]
+    pub fn start(input_len: Option<u64>) -> Self {
+        let progress = Arc::new(Mutex::new(Progress::new(input_len)));
+        let (stop, stopped) = mpsc::channel();
+        let shared = Arc::clone(&progress);
+        let thread = thread::spawn(move || {
+            let mut drawn = 0;
+            loop {
+                let res = stopped.recv_timeout(Duration::from_millis(200));
+                let lines = {
+                    // NOTE keep drawing even if an update panicked
+                    let progress = shared.lock().unwrap_or_else(PoisonError::into_inner);
+                    progress.render(progress.start.elapsed())
+                };
+                let mut err = io::stderr().lock();
+                // move to the start of the previous dashboard and clear it
+                if drawn > 0 {
+                    let _ = write!(err, "\x1b[{drawn}F");
+                }
+                let _ = write!(err, "\x1b[J");
+                for line in &lines {
+                    let _ = writeln!(err, "{line}");
+                }
+                drawn = lines.len();
+                if res != Err(mpsc::RecvTimeoutError::Timeout) {
+                    return;
+                }
+            }
+        });
+        Dashboard {
+            progress,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Update the progress shown
+    ///
+    /// # Panics
+    ///
+    /// If the thread drawing the dashboard panicked.
+    pub fn update(&self, update: impl FnOnce(&mut Progress)) {
+        update(&mut self.progress.lock().unwrap());
+    }
+
+    /// Wrap `inner` so that children that exit unsuccessfully are counted as failed
+    pub fn watch<S: Spawn>(&self, inner: S) -> Watched<S> {
+        Watched {
+            inner,
+            progress: Arc::clone(&self.progress),
+        }
+    }
+
+    /// Draw the dashboard a final time and stop redrawing it
+    ///
+    /// # Panics
+    ///
+    /// If the thread drawing the dashboard panicked.
+    pub fn finish(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+/// A spawner that counts the children that exit unsuccessfully on a dashboard
+#[derive(Debug)]
+pub struct Watched<S> {
+    inner: S,
+    progress: Arc<Mutex<Progress>>,
+}
+
+impl<S: Spawn> Spawn for Watched<S> {
+    fn command(&mut self) -> &mut Command {
+        self.inner.command()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        self.inner.spawn(slot)
+    }
+
+    fn wait(&mut self, child: Child) -> Result<(), Error> {
+        let pid = child.id();
+        let res = self.inner.wait(child);
+        if let Err(err @ (Error::NonZeroExitCode(_) | Error::KilledBySignal)) = &res {
+            self.progress
+                .lock()
+                .unwrap()
+                .fail(&format!("pid {pid}: {err}"));
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_bytes, Progress, ERRORS, WORKERS};
+    use std::time::Duration;
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(format_bytes(12), "12 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 << 30), "3.0 GiB");
+    }
+
+    #[test]
+    fn renders_progress() {
+        let mut progress = Progress::new(Some(40));
+        progress.record(6, 5, 0, 10);
+        progress.record(4, 3, 1, 11);
+        progress.record(6, 5, 0, 12);
+        progress.drop_record(4, &"broken pipe");
+        progress.fail(&"exit code 1");
+        let lines = progress.render(Duration::from_secs(2));
+        assert_eq!(lines[0], "xstream  2.0s   50.0% of 40 B read");
+        assert_eq!(lines[1], "records  3 sent  1 dropped  children  1 failed");
+        assert!(lines[2].starts_with("worker 0    12       [###"));
+        assert!(lines[2].ends_with("10 B        5 B/s        2 records"));
+        assert!(lines[3].starts_with("worker 1    11       [######### "));
+        assert_eq!(
+            lines[4..],
+            ["recent errors:", "  broken pipe", "  exit code 1"]
+        );
+    }
+
+    #[test]
+    fn renders_recent() {
+        let mut progress = Progress::new(None);
+        for slot in 0..20 {
+            progress.record(1, 1, slot, 100);
+        }
+        for err in 0..10 {
+            progress.error(&err);
+        }
+        let lines = progress.render(Duration::from_secs(1));
+        assert_eq!(lines[0], "xstream  1.0s  20 B read");
+        assert!(lines[2].starts_with("worker 0 "));
+        assert_eq!(
+            lines[2 + WORKERS],
+            format!("... and {} more workers", 20 - WORKERS)
+        );
+        assert_eq!(lines.len(), 4 + WORKERS + ERRORS);
+        assert_eq!(lines.last().unwrap(), "  9");
+    }
+}
@@ -0,0 +1,138 @@
+//! Pseudo-terminal output for child processes
+//!
+//! Some programs change their behavior when their output isn't a terminal, for example by fully
+//! buffering their output or disabling color. This attaches the stdout of every child to a new
+//! pseudo-terminal, and relays everything written to it to the stdout of this process.
+//!
+//! The stdin of each child is still a pipe. A terminal would interpret control characters in the
+//! input, and closing one hangs up on the child instead of sending it the end of its input.
+use super::pool::{Error, Spawn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::panic;
+use std::process::{Child, Command, Stdio};
+use std::ptr;
+use std::thread::{self, JoinHandle};
+
+/// A spawner that attaches the stdout of every child to a pseudo-terminal
+#[derive(Debug)]
+pub struct Pty<S> {
+    inner: S,
+    relays: HashMap<u32, JoinHandle<io::Result<()>>>,
+}
+
+impl<S: Spawn> Pty<S> {
+    /// Wrap `inner` so that children it spawns write to a pseudo-terminal
+    ///
+    /// Any stdout configured on the command of `inner` will be replaced.
+    pub fn new(mut inner: S) -> Self {
+        // SAFETY: setsid and ioctl are async signal safe
+        unsafe {
+            inner.command().pre_exec(|| {
+                // become a session leader so the terminal can be made our controlling terminal
+                if libc::setsid() < 0 || libc::ioctl(1, libc::TIOCSCTTY, 0) < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            });
+        }
+        Pty {
+            inner,
+            relays: HashMap::new(),
+        }
+    }
+}
+
+impl<S: Spawn> Spawn for Pty<S> {
+    fn command(&mut self) -> &mut Command {
+        self.inner.command()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        let (master, slave) = open_pty().map_err(Error::Spawn)?;
+        self.inner.command().stdout(slave);
+        let res = self.inner.spawn(slot);
+        // NOTE we need to close our copy of the slave so that reading from the master ends when
+        // the child exits
+        self.inner.command().stdout(Stdio::inherit());
+        let child = res?;
+        self.relays
+            .insert(child.id(), thread::spawn(move || relay(master)));
+        Ok(child)
+    }
+
+    fn wait(&mut self, child: Child) -> Result<(), Error> {
+        let relay = self.relays.remove(&child.id());
+        let res = self.inner.wait(child);
+        if let Some(relay) = relay {
+            relay
+                .join()
+                .unwrap_or_else(|err| panic::resume_unwind(err))
+                .map_err(Error::Relay)?;
+        }
+        res
+    }
+}
+
+/// Open a new pseudo-terminal that doesn't alter anything written to it
+fn open_pty() -> io::Result<(File, OwnedFd)> {
+    let mut master = -1;
+    let mut slave = -1;
+    // SAFETY: all pointers are valid, and the returned file descriptors are immediately owned
+    let (master, slave) = unsafe {
+        if libc::openpty(
+            &raw mut master,
+            &raw mut slave,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave))
+    };
+    // SAFETY: the file descriptors are valid, and attrs is initialized by tcgetattr
+    unsafe {
+        for fd in [&master, &slave] {
+            if libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        let mut attrs = MaybeUninit::uninit();
+        if libc::tcgetattr(slave.as_raw_fd(), attrs.as_mut_ptr()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut attrs = attrs.assume_init();
+        // raw mode prevents translating newlines to carriage return newlines
+        libc::cfmakeraw(&raw mut attrs);
+        if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &raw const attrs) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok((File::from(master), slave))
+}
+
+/// Copy everything from the master side of a pseudo-terminal to stdout
+fn relay(mut master: File) -> io::Result<()> {
+    let mut buf = [0; 8192];
+    loop {
+        match master.read(&mut buf) {
+            // reading from a master fails with EIO once every slave has been closed
+            Ok(0) => return Ok(()),
+            Err(err) if err.raw_os_error() == Some(libc::EIO) => return Ok(()),
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+            Ok(len) => {
+                let mut out = io::stdout().lock();
+                out.write_all(&buf[..len])?;
+                out.flush()?;
+            }
+        }
+    }
+}
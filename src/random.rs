@@ -0,0 +1,162 @@
+//! Random process pool
+//!
+//! This pool will spawn up to a set number of processes, and then return a uniformly random one
+//! for every request. Compared to round-robin, this avoids pathological input orderings, like
+//! every large record landing on the same process. To effectively manage cleanup, this needs to
+//! be dropped, so panics while using this may result in zombie processes.
+use super::checkpoint::Routing;
+use super::pool::{Error, Pool, Spawn};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::mem;
+use std::process::Child;
+
+/// Generate a random index less than `len` using splitmix64, advancing `state`
+pub(crate) fn random_index(state: &mut u64, len: usize) -> usize {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut rand = *state;
+    rand = (rand ^ (rand >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    rand = (rand ^ (rand >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    rand ^= rand >> 31;
+    // NOTE multiplying and shifting avoids the modulo bias, and always fits in a usize
+    #[allow(clippy::cast_possible_truncation)]
+    let ind = ((u128::from(rand) * len as u128) >> 64) as usize;
+    ind
+}
+
+/// A pool that returns random processes
+///
+/// This pool will return new processes up to the limit, and then start returning old processes
+/// chosen uniformly at random. The first `max_procs` records therefore always go to freshly
+/// spawned processes in order, and only later records are assigned randomly. Like `Rotating`,
+/// this is most effective if each process handles every delimited task it's sent.
+#[derive(Debug)]
+pub struct Random<S> {
+    procs: Vec<Child>,
+    max_procs: usize,
+    spawner: S,
+    state: u64,
+    slot: usize,
+}
+
+impl<S: Spawn> Random<S> {
+    /// Create a new empty pool with a limited number of total processes and a random seed
+    ///
+    /// `spawner` is usually a `Command`. Set `max_procs` to 0 to enable unbounded parallelism.
+    pub fn new(spawner: S, max_procs: usize) -> Self {
+        let seed = RandomState::new().build_hasher().finish();
+        Self::with_seed(spawner, max_procs, seed)
+    }
+
+    /// Create a new empty pool with a fixed seed
+    ///
+    /// Pools with the same seed will select processes in the same order.
+    pub fn with_seed(spawner: S, max_procs: usize, seed: u64) -> Self {
+        Random {
+            procs: Vec::with_capacity(max_procs),
+            max_procs,
+            spawner,
+            state: seed,
+            slot: 0,
+        }
+    }
+
+    /// Create a pool of processes that were already spawned, with a random seed
+    ///
+    /// Like `Rotating::with_children`, `spawner` is used to wait for `children`, and to spawn a
+    /// replacement when one is respawned. Every child must have a piped stdin.
+    ///
+    /// # Panics
+    ///
+    /// If `children` is empty.
+    pub fn with_children(spawner: S, children: impl IntoIterator<Item = Child>) -> Self {
+        let procs: Vec<_> = children.into_iter().collect();
+        assert!(!procs.is_empty(), "a pool needs at least one child");
+        let mut pool = Self::new(spawner, procs.len());
+        pool.procs = procs;
+        pool
+    }
+
+    /// Create a new empty pool that continues routing from a checkpoint
+    ///
+    /// Returns `None` if `routing` isn't for a `Random` pool.
+    pub fn with_routing(spawner: S, routing: Routing) -> Option<Self> {
+        match routing {
+            Routing::Random { procs, state } => Some(Self::with_seed(spawner, procs, state)),
+            _ => None,
+        }
+    }
+
+    /// Generate a random index less than `len`
+    fn index(&mut self, len: usize) -> usize {
+        random_index(&mut self.state, len)
+    }
+}
+
+impl<S: Spawn> Pool for Random<S> {
+    /// Get a process from the pool
+    ///
+    /// If fewer than `max_procs` have been spawned, this will spawn a new process, otherwise it
+    /// will return a random one that was already spawned.
+    fn get(&mut self) -> Result<&mut Child, Error> {
+        if self.max_procs == 0 || self.procs.len() < self.max_procs {
+            let proc = self.spawner.spawn(self.procs.len())?;
+            self.slot = self.procs.len();
+            self.procs.push(proc);
+            Ok(self.procs.last_mut().unwrap()) // just pushed
+        } else {
+            self.slot = self.index(self.procs.len());
+            Ok(&mut self.procs[self.slot])
+        }
+    }
+
+    /// The index of the process most recently returned
+    fn slot(&self) -> usize {
+        self.slot
+    }
+
+    /// Replace the process most recently returned
+    fn respawn(&mut self) -> Result<&mut Child, Error> {
+        if self.procs.len() <= self.slot {
+            return self.get();
+        }
+        let proc = self.spawner.spawn(self.slot)?;
+        let old = mem::replace(&mut self.procs[self.slot], proc);
+        self.spawner.wait(old)?;
+        Ok(&mut self.procs[self.slot])
+    }
+
+    fn routing(&self) -> Option<Routing> {
+        Some(Routing::Random {
+            procs: self.max_procs,
+            state: self.state,
+        })
+    }
+
+    /// Wait for all processes to finish successfully
+    ///
+    /// Errors will terminate early and not wait for reamining processes to finish. To continue
+    /// waiting for them anyway you can continue to call join until you get a success, this will
+    /// indicate that there are no more running processes under management by the pool.
+    fn join(&mut self) -> Result<(), Error> {
+        // NOTE we do this instead of drain so that errors don't drop the rest of our processes
+        // creating zombies
+        while let Some(proc) = self.procs.pop() {
+            self.spawner.wait(proc)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S> Drop for Random<S> {
+    fn drop(&mut self) {
+        // kill any children left in self
+        for proc in &mut self.procs {
+            let _ = proc.kill();
+        }
+        // wait for them to be cleaned up
+        for proc in &mut self.procs {
+            let _ = proc.wait();
+        }
+    }
+}
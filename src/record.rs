@@ -0,0 +1,169 @@
+//! Records passed to child processes outside of stdin
+//!
+//! Some commands don't read their input from stdin, but are configured with environment
+//! variables or the path of a file instead. These pass the record a child is spawned for to it
+//! directly, so every record gets its own child.
+use super::pool::{Error, Spawn};
+use std::cell::RefCell;
+use std::ffi::{OsStr, OsString};
+#[cfg(target_os = "linux")]
+use std::fs::File;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::io::{Seek, Write};
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::ffi::OsStrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::rc::Rc;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicI32, Ordering};
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+
+/// A spawner that passes the current record to new children in an environment variable
+///
+/// The record is whatever is in the shared buffer when a child is spawned, so it should be
+/// updated with every record before it's sent.
+#[derive(Debug)]
+pub struct RecordEnv<S> {
+    inner: S,
+    name: OsString,
+    max_size: u64,
+    record: Rc<RefCell<Vec<u8>>>,
+}
+
+impl<S: Spawn> RecordEnv<S> {
+    /// Wrap `inner` so that its children get `record` in the variable `name`
+    ///
+    /// Spawning fails if the record is larger than `max_size` bytes, since the environment of a
+    /// process is limited in size.
+    pub fn new(
+        inner: S,
+        name: impl Into<OsString>,
+        max_size: u64,
+        record: Rc<RefCell<Vec<u8>>>,
+    ) -> Self {
+        RecordEnv {
+            inner,
+            name: name.into(),
+            max_size,
+            record,
+        }
+    }
+}
+
+impl<S: Spawn> Spawn for RecordEnv<S> {
+    fn command(&mut self) -> &mut Command {
+        self.inner.command()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        let record = self.record.borrow();
+        if record.len() as u64 > self.max_size {
+            return Err(Error::Spawn(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "a record of {} bytes is too large to pass in {}",
+                    record.len(),
+                    self.name.to_string_lossy()
+                ),
+            )));
+        }
+        self.inner
+            .command()
+            .env(&self.name, OsStr::from_bytes(&record));
+        self.inner.spawn(slot)
+    }
+
+    fn wait(&mut self, child: Child) -> Result<(), Error> {
+        self.inner.wait(child)
+    }
+}
+
+/// The file descriptor children can read their record from
+#[cfg(target_os = "linux")]
+pub const RECORD_FD: i32 = 3;
+
+/// A spawner that passes the current record to new children as an in-memory file
+///
+/// Children can read the record from [`RECORD_FD`], e.g. at `/dev/fd/3`. The file is sealed, so
+/// children can't change it, and it's never written to disk.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct RecordFile<S> {
+    inner: S,
+    record: Rc<RefCell<Vec<u8>>>,
+    fd: Arc<AtomicI32>,
+}
+
+#[cfg(target_os = "linux")]
+impl<S: Spawn> RecordFile<S> {
+    /// Wrap `inner` so that its children get `record` as an in-memory file
+    pub fn new(mut inner: S, record: Rc<RefCell<Vec<u8>>>) -> Self {
+        let fd = Arc::new(AtomicI32::new(-1));
+        let child_fd = Arc::clone(&fd);
+        // SAFETY: dup2 and fcntl are async signal safe
+        unsafe {
+            inner.command().pre_exec(move || {
+                let fd = child_fd.load(Ordering::SeqCst);
+                // dup2 clears close on exec, unless the descriptor is already in place
+                let res = if fd == RECORD_FD {
+                    libc::fcntl(fd, libc::F_SETFD, 0)
+                } else {
+                    libc::dup2(fd, RECORD_FD)
+                };
+                if res < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            });
+        }
+        RecordFile { inner, record, fd }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<S: Spawn> Spawn for RecordFile<S> {
+    fn command(&mut self) -> &mut Command {
+        self.inner.command()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        let file = record_memfd(&self.record.borrow()).map_err(Error::Spawn)?;
+        self.fd.store(file.as_raw_fd(), Ordering::SeqCst);
+        // NOTE our copy of the file is closed once the child has its own
+        self.inner.spawn(slot)
+    }
+
+    fn wait(&mut self, child: Child) -> Result<(), Error> {
+        self.inner.wait(child)
+    }
+}
+
+/// Create a sealed in-memory file containing `record`
+#[cfg(target_os = "linux")]
+fn record_memfd(record: &[u8]) -> io::Result<File> {
+    // SAFETY: the name is a valid c string, and the returned descriptor is immediately owned
+    let mut file = unsafe {
+        let fd = libc::memfd_create(
+            c"xstream-record".as_ptr(),
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        File::from_raw_fd(fd)
+    };
+    file.write_all(record)?;
+    file.rewind()?;
+    let seals = libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+    // SAFETY: the file descriptor is valid
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
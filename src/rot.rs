@@ -3,10 +3,10 @@
 //! This pool will spawn up to a set number of processes, and then start returning old processes in
 //! a round robin fashion.  To effectively manage cleanup, this needs to be dropped, so panics
 //! while using this may result in zombie processes.
-use super::pool;
-use super::pool::{Error, Pool};
-use std::borrow::BorrowMut;
-use std::process::{Child, Command, Stdio};
+use super::checkpoint::Routing;
+use super::pool::{Error, Pool, Spawn};
+use std::mem;
+use std::process::Child;
 
 /// A pool to manage spawning a limited number of processses
 ///
@@ -15,46 +15,76 @@ use std::process::{Child, Command, Stdio};
 /// delimiters as well, allowing for better utilization of resources for embarassingly parallel
 /// tasks.
 #[derive(Debug)]
-pub struct Rotating<C> {
+pub struct Rotating<S> {
     procs: Vec<Child>,
     max_procs: usize,
-    command: C,
+    spawner: S,
     ind: usize,
 }
 
-impl<C: BorrowMut<Command>> Rotating<C> {
+impl<S: Spawn> Rotating<S> {
     /// Create a new empty pool with a limited number of total processes
     ///
-    /// Set `max_procs` to 0 to enable unbounded parallelism.
-    pub fn new(mut command: C, max_procs: usize) -> Self {
-        command.borrow_mut().stdin(Stdio::piped());
+    /// `spawner` is usually a `Command`. Set `max_procs` to 0 to enable unbounded parallelism.
+    pub fn new(spawner: S, max_procs: usize) -> Self {
         Self {
             procs: Vec::with_capacity(max_procs),
             max_procs,
-            command,
+            spawner,
             ind: 0,
         }
     }
 
-    /// Spawn a new process
-    fn spawn(&mut self) -> Result<Child, Error> {
-        self.command.borrow_mut().spawn().map_err(Error::Spawn)
+    /// Create a pool of processes that were already spawned
+    ///
+    /// This is useful when children need setup that a `Command` can't express, e.g. running in a
+    /// container. The pool has one process for every child, so `spawner` is used to wait for
+    /// `children`, and to spawn a replacement when one is respawned. It should spawn processes
+    /// that are interchangeable with `children`. Every child must have a piped stdin.
+    ///
+    /// # Panics
+    ///
+    /// If `children` is empty.
+    pub fn with_children(spawner: S, children: impl IntoIterator<Item = Child>) -> Self {
+        let procs: Vec<_> = children.into_iter().collect();
+        assert!(!procs.is_empty(), "a pool needs at least one child");
+        Self {
+            max_procs: procs.len(),
+            procs,
+            spawner,
+            ind: 0,
+        }
+    }
+
+    /// Create a new empty pool that continues routing from a checkpoint
+    ///
+    /// Returns `None` if `routing` isn't for a `Rotating` pool.
+    pub fn with_routing(spawner: S, routing: Routing) -> Option<Self> {
+        match routing {
+            Routing::Rotating { procs, next } if next < procs.max(1) => {
+                let mut pool = Self::new(spawner, procs);
+                pool.ind = next;
+                Some(pool)
+            }
+            _ => None,
+        }
     }
 }
 
-impl<C: BorrowMut<Command>> Pool for Rotating<C> {
+impl<S: Spawn> Pool for Rotating<S> {
     /// Get a process from the pool
     ///
     /// If fewer than `max_procs` have been spawned, this will spawn a new process, otherwise it
     /// will return one that was already spawned.
     fn get(&mut self) -> Result<&mut Child, Error> {
         if self.max_procs == 0 {
-            let proc = self.spawn()?;
+            let proc = self.spawner.spawn(self.procs.len())?;
             self.procs.push(proc);
             Ok(self.procs.last_mut().unwrap())
         } else {
-            if self.procs.len() < self.max_procs {
-                let proc = self.spawn()?;
+            // NOTE a restored pool may need to spawn processes before the next one
+            while self.procs.len() <= self.ind {
+                let proc = self.spawner.spawn(self.procs.len())?;
                 self.procs.push(proc);
             }
             let child = &mut self.procs[self.ind];
@@ -64,6 +94,34 @@ impl<C: BorrowMut<Command>> Pool for Rotating<C> {
         }
     }
 
+    /// The index of the process most recently returned
+    fn slot(&self) -> usize {
+        if self.max_procs == 0 {
+            self.procs.len().saturating_sub(1)
+        } else {
+            (self.ind + self.max_procs - 1) % self.max_procs
+        }
+    }
+
+    /// Replace the process most recently returned
+    fn respawn(&mut self) -> Result<&mut Child, Error> {
+        let slot = self.slot();
+        if self.procs.len() <= slot {
+            return self.get();
+        }
+        let proc = self.spawner.spawn(slot)?;
+        let old = mem::replace(&mut self.procs[slot], proc);
+        self.spawner.wait(old)?;
+        Ok(&mut self.procs[slot])
+    }
+
+    fn routing(&self) -> Option<Routing> {
+        Some(Routing::Rotating {
+            procs: self.max_procs,
+            next: self.ind,
+        })
+    }
+
     /// Wait for all processes to finish successfully
     ///
     /// Errors will terminate early and not wait for reamining processes to finish. To continue
@@ -73,13 +131,13 @@ impl<C: BorrowMut<Command>> Pool for Rotating<C> {
         // NOTE we do this instead of drain so that errors don't drop the rest of our processes
         // creating zombies
         while let Some(proc) = self.procs.pop() {
-            pool::wait_proc(proc)?;
+            self.spawner.wait(proc)?;
         }
         Ok(())
     }
 }
 
-impl<C> Drop for Rotating<C> {
+impl<S> Drop for Rotating<S> {
     fn drop(&mut self) {
         // kill any children left in self
         for proc in &mut self.procs {
@@ -0,0 +1,615 @@
+//! A small language for choosing the worker for each record
+//!
+//! Fixed strategies like round-robin or hashing a key cover most needs, but not combinations of
+//! them, e.g. sending large records to a dedicated worker. Routes are parsed once and evaluated
+//! against every record without spawning anything.
+//!
+//! A route is an arithmetic expression of unsigned integers with `+`, `-`, `*`, `/`, and `%`,
+//! which can be chosen with `if <condition> then <route> else <route>`. Conditions compare two
+//! expressions with `==`, `!=`, `<`, `<=`, `>`, or `>=`, and can be combined with `and` and `or`,
+//! where `and` binds tighter. A combination is decided by either side, even if the other can't be
+//! evaluated, e.g. `num(field(1)) > 9 or len == 0` is true for an empty record. Numbers can have a
+//! binary size suffix like `4k` or `1M`. The values available are:
+//!
+//! - `len`: the length of the record in bytes
+//! - `workers`: the number of workers
+//! - `field(n)`: the `n`th whitespace separated field of the record starting from one, or the
+//!   whole record if `n` is zero, with `field(n, ",")` to separate fields by a string instead
+//! - `hash(field(n))`: the stable hash of a field, which `Partitioned` pools also use for keys
+//! - `num(field(n))`: a field parsed as a decimal number
+//! - `rr()`: the next worker in round-robin order
+//! - `worker(n)`: worker `n`, which is the same as `n`
+use super::part::hash_key;
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use std::str::{self, FromStr};
+
+/// An error parsing a route, with the byte offset it was found at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseRouteError {
+    message: String,
+    offset: usize,
+}
+
+impl ParseRouteError {
+    fn new(message: impl Into<String>, offset: usize) -> Self {
+        ParseRouteError {
+            message: message.into(),
+            offset,
+        }
+    }
+}
+
+impl Display for ParseRouteError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(fmt, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl error::Error for ParseRouteError {}
+
+/// The smallest pieces of a route
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'a> {
+    Num(u64),
+    Ident(&'a str),
+    Str(Vec<u8>),
+    Punct(&'static str),
+}
+
+/// Punctuation, with longer tokens before their prefixes
+const PUNCTS: [&str; 14] = [
+    "==", "!=", ">=", "<=", ">", "<", "(", ")", ",", "+", "-", "*", "/", "%",
+];
+
+/// Split a route into tokens and their offsets
+fn tokenize(src: &str) -> Result<Vec<(usize, Token<'_>)>, ParseRouteError> {
+    let bytes = src.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while let Some(&byte) = bytes.get(pos) {
+        let start = pos;
+        let token = if byte.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        } else if byte.is_ascii_digit() {
+            while bytes.get(pos).is_some_and(u8::is_ascii_digit) {
+                pos += 1;
+            }
+            let num: u64 = src[start..pos]
+                .parse()
+                .map_err(|_| ParseRouteError::new("number too large", start))?;
+            let scale = match bytes.get(pos).map(u8::to_ascii_lowercase) {
+                Some(b'k') => 1 << 10,
+                Some(b'm') => 1 << 20,
+                Some(b'g') => 1 << 30,
+                Some(b't') => 1 << 40,
+                _ => 1,
+            };
+            if scale > 1 {
+                pos += 1;
+            }
+            Token::Num(
+                num.checked_mul(scale)
+                    .ok_or_else(|| ParseRouteError::new("number too large", start))?,
+            )
+        } else if byte.is_ascii_alphabetic() || byte == b'_' {
+            while bytes
+                .get(pos)
+                .is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'_')
+            {
+                pos += 1;
+            }
+            Token::Ident(&src[start..pos])
+        } else if byte == b'"' {
+            pos += 1;
+            let mut string = Vec::new();
+            loop {
+                match bytes.get(pos) {
+                    None => return Err(ParseRouteError::new("unterminated string", start)),
+                    Some(b'"') => break,
+                    Some(b'\\') => {
+                        string.push(match bytes.get(pos + 1) {
+                            Some(b'n') => b'\n',
+                            Some(b't') => b'\t',
+                            Some(&escaped @ (b'"' | b'\\')) => escaped,
+                            _ => return Err(ParseRouteError::new("invalid escape", pos)),
+                        });
+                        pos += 1;
+                    }
+                    Some(&byte) => string.push(byte),
+                }
+                pos += 1;
+            }
+            pos += 1;
+            Token::Str(string)
+        } else {
+            let punct = PUNCTS
+                .into_iter()
+                .find(|punct| src[pos..].starts_with(punct))
+                .ok_or_else(|| {
+                    let chr = src[pos..].chars().next().unwrap_or_default();
+                    ParseRouteError::new(format!("unexpected character {chr:?}"), pos)
+                })?;
+            pos += punct.len();
+            Token::Punct(punct)
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+/// An arithmetic operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// A comparison operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// An expression that evaluates to a number
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Num {
+    Lit(u64),
+    Len,
+    Workers,
+    Hash(Field),
+    Parse(Field),
+    RoundRobin,
+    Op(Op, Box<Num>, Box<Num>),
+    If(Box<Cond>, Box<Num>, Box<Num>),
+}
+
+/// A field of a record, and what separates it from the others
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Field {
+    index: Box<Num>,
+    sep: Option<Vec<u8>>,
+}
+
+/// An expression that evaluates to a boolean
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Cond {
+    Cmp(Cmp, Num, Num),
+    And(Box<Cond>, Box<Cond>),
+    Or(Box<Cond>, Box<Cond>),
+}
+
+/// What routes are evaluated against
+struct Context<'a> {
+    record: &'a [u8],
+    workers: usize,
+    next: &'a mut usize,
+}
+
+impl Num {
+    fn eval(&self, ctx: &mut Context<'_>) -> Option<u64> {
+        match self {
+            Num::Lit(num) => Some(*num),
+            Num::Len => Some(ctx.record.len() as u64),
+            Num::Workers => Some(ctx.workers as u64),
+            Num::Hash(field) => Some(hash_key(field.eval(ctx)?)),
+            Num::Parse(field) => str::from_utf8(field.eval(ctx)?).ok()?.trim().parse().ok(),
+            Num::RoundRobin => {
+                let ind = *ctx.next % ctx.workers;
+                *ctx.next = ind + 1;
+                Some(ind as u64)
+            }
+            Num::Op(op, left, right) => {
+                let (left, right) = (left.eval(ctx)?, right.eval(ctx)?);
+                match op {
+                    Op::Add => left.checked_add(right),
+                    Op::Sub => left.checked_sub(right),
+                    Op::Mul => left.checked_mul(right),
+                    Op::Div => left.checked_div(right),
+                    Op::Rem => left.checked_rem(right),
+                }
+            }
+            Num::If(cond, then, other) => {
+                if cond.eval(ctx)? {
+                    then.eval(ctx)
+                } else {
+                    other.eval(ctx)
+                }
+            }
+        }
+    }
+}
+
+impl Field {
+    fn eval<'a>(&self, ctx: &mut Context<'a>) -> Option<&'a [u8]> {
+        let record = ctx.record;
+        let index = usize::try_from(self.index.eval(ctx)?).ok()?;
+        let Some(prev) = index.checked_sub(1) else {
+            return Some(record);
+        };
+        match &self.sep {
+            None => record
+                .split(u8::is_ascii_whitespace)
+                .filter(|field| !field.is_empty())
+                .nth(prev),
+            Some(sep) => nth_field(record, sep, prev),
+        }
+    }
+}
+
+/// The field of `record` after `skip` occurrences of `sep`
+fn nth_field<'a>(mut record: &'a [u8], sep: &[u8], skip: usize) -> Option<&'a [u8]> {
+    for _ in 0..skip {
+        let pos = record.windows(sep.len()).position(|w| w == sep)?;
+        record = &record[pos + sep.len()..];
+    }
+    let end = record
+        .windows(sep.len())
+        .position(|w| w == sep)
+        .unwrap_or(record.len());
+    Some(&record[..end])
+}
+
+impl Cond {
+    fn eval(&self, ctx: &mut Context<'_>) -> Option<bool> {
+        match self {
+            Cond::Cmp(cmp, left, right) => {
+                let (left, right) = (left.eval(ctx)?, right.eval(ctx)?);
+                Some(match cmp {
+                    Cmp::Eq => left == right,
+                    Cmp::Ne => left != right,
+                    Cmp::Lt => left < right,
+                    Cmp::Le => left <= right,
+                    Cmp::Gt => left > right,
+                    Cmp::Ge => left >= right,
+                })
+            }
+            // NOTE a side that can't be evaluated only matters if the other side doesn't decide
+            Cond::And(left, right) => match left.eval(ctx) {
+                Some(false) => Some(false),
+                left => match (left, right.eval(ctx)) {
+                    (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                },
+            },
+            Cond::Or(left, right) => match left.eval(ctx) {
+                Some(true) => Some(true),
+                left => match (left, right.eval(ctx)) {
+                    (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                },
+            },
+        }
+    }
+}
+
+/// A recursive descent parser of tokens
+struct Parser<'a> {
+    tokens: Vec<(usize, Token<'a>)>,
+    pos: usize,
+    end: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// The offset of the next token
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.end, |(offset, _)| *offset)
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseRouteError {
+        ParseRouteError::new(message, self.offset())
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.pos)?.1.clone();
+        self.pos += 1;
+        Some(token)
+    }
+
+    /// Consume the next token if it's `expected`
+    fn eat(&mut self, expected: &Token<'_>) -> bool {
+        let matches = self
+            .tokens
+            .get(self.pos)
+            .is_some_and(|(_, token)| token == expected);
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, expected: &Token<'_>, name: &str) -> Result<(), ParseRouteError> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {name}")))
+        }
+    }
+
+    fn route(&mut self) -> Result<Num, ParseRouteError> {
+        if self.eat(&Token::Ident("if")) {
+            let cond = self.or()?;
+            self.expect(&Token::Ident("then"), "then")?;
+            let then = self.route()?;
+            self.expect(&Token::Ident("else"), "else")?;
+            let other = self.route()?;
+            Ok(Num::If(Box::new(cond), Box::new(then), Box::new(other)))
+        } else {
+            self.sum()
+        }
+    }
+
+    fn or(&mut self) -> Result<Cond, ParseRouteError> {
+        let mut cond = self.and()?;
+        while self.eat(&Token::Ident("or")) {
+            cond = Cond::Or(Box::new(cond), Box::new(self.and()?));
+        }
+        Ok(cond)
+    }
+
+    fn and(&mut self) -> Result<Cond, ParseRouteError> {
+        let mut cond = self.cmp()?;
+        while self.eat(&Token::Ident("and")) {
+            cond = Cond::And(Box::new(cond), Box::new(self.cmp()?));
+        }
+        Ok(cond)
+    }
+
+    fn cmp(&mut self) -> Result<Cond, ParseRouteError> {
+        let left = self.sum()?;
+        let cmp = match self.tokens.get(self.pos) {
+            Some((_, Token::Punct("=="))) => Cmp::Eq,
+            Some((_, Token::Punct("!="))) => Cmp::Ne,
+            Some((_, Token::Punct("<"))) => Cmp::Lt,
+            Some((_, Token::Punct("<="))) => Cmp::Le,
+            Some((_, Token::Punct(">"))) => Cmp::Gt,
+            Some((_, Token::Punct(">="))) => Cmp::Ge,
+            _ => return Err(self.error("expected a comparison")),
+        };
+        self.pos += 1;
+        Ok(Cond::Cmp(cmp, left, self.sum()?))
+    }
+
+    fn sum(&mut self) -> Result<Num, ParseRouteError> {
+        let mut num = self.product()?;
+        loop {
+            let op = if self.eat(&Token::Punct("+")) {
+                Op::Add
+            } else if self.eat(&Token::Punct("-")) {
+                Op::Sub
+            } else {
+                return Ok(num);
+            };
+            num = Num::Op(op, Box::new(num), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Num, ParseRouteError> {
+        let mut num = self.atom()?;
+        loop {
+            let op = if self.eat(&Token::Punct("*")) {
+                Op::Mul
+            } else if self.eat(&Token::Punct("/")) {
+                Op::Div
+            } else if self.eat(&Token::Punct("%")) {
+                Op::Rem
+            } else {
+                return Ok(num);
+            };
+            num = Num::Op(op, Box::new(num), Box::new(self.atom()?));
+        }
+    }
+
+    fn atom(&mut self) -> Result<Num, ParseRouteError> {
+        let offset = self.offset();
+        let num = match self.next() {
+            Some(Token::Num(num)) => Num::Lit(num),
+            Some(Token::Ident("len")) => Num::Len,
+            Some(Token::Ident("workers")) => Num::Workers,
+            Some(Token::Punct("(")) => {
+                let num = self.route()?;
+                self.expect(&Token::Punct(")"), ")")?;
+                num
+            }
+            Some(Token::Ident(name)) if self.eat(&Token::Punct("(")) => {
+                let num = match name {
+                    "hash" => Num::Hash(self.field()?),
+                    "num" => Num::Parse(self.field()?),
+                    "rr" => Num::RoundRobin,
+                    "worker" => self.route()?,
+                    _ => {
+                        let message = format!("unknown function {name}");
+                        return Err(ParseRouteError::new(message, offset));
+                    }
+                };
+                self.expect(&Token::Punct(")"), ")")?;
+                num
+            }
+            _ => {
+                let message = "expected a number, len, workers, or a function";
+                return Err(ParseRouteError::new(message, offset));
+            }
+        };
+        Ok(num)
+    }
+
+    fn field(&mut self) -> Result<Field, ParseRouteError> {
+        self.expect(&Token::Ident("field"), "field")?;
+        self.expect(&Token::Punct("("), "(")?;
+        let index = Box::new(self.route()?);
+        let sep = if self.eat(&Token::Punct(",")) {
+            let offset = self.offset();
+            match self.next() {
+                Some(Token::Str(sep)) if !sep.is_empty() => Some(sep),
+                _ => return Err(ParseRouteError::new("expected a separator", offset)),
+            }
+        } else {
+            None
+        };
+        self.expect(&Token::Punct(")"), ")")?;
+        Ok(Field { index, sep })
+    }
+}
+
+/// A parsed route that chooses the worker for each record
+///
+/// See the [module documentation](self) for the syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    num: Num,
+    next: usize,
+}
+
+impl Route {
+    /// The state of `rr()`, which is the next worker it evaluates to before wrapping around
+    pub(crate) fn rr(&self) -> usize {
+        self.next
+    }
+
+    /// Continue `rr()` from the state returned by [`Route::rr`]
+    pub(crate) fn set_rr(&mut self, next: usize) {
+        self.next = next;
+    }
+
+    /// The worker out of `workers` that gets `record`
+    ///
+    /// Routes that are larger than the number of workers wrap around. Returns `None` if the route
+    /// can't be evaluated for this record, e.g. if a field doesn't exist or isn't a number, or
+    /// there's a division by zero.
+    ///
+    /// # Panics
+    ///
+    /// If `workers` is zero.
+    pub fn worker(&mut self, record: &[u8], workers: usize) -> Option<usize> {
+        assert_ne!(workers, 0, "routes need at least one worker");
+        let mut ctx = Context {
+            record,
+            workers,
+            next: &mut self.next,
+        };
+        let num = self.num.eval(&mut ctx)?;
+        // NOTE the remainder is less than the number of workers so it fits in a usize
+        #[allow(clippy::cast_possible_truncation)]
+        let ind = (num % workers as u64) as usize;
+        Some(ind)
+    }
+}
+
+impl FromStr for Route {
+    type Err = ParseRouteError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+            end: src.len(),
+        };
+        let num = parser.route()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(parser.error("unexpected trailing input"));
+        }
+        Ok(Route { num, next: 0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParseRouteError, Route};
+    use crate::part::partition;
+
+    fn route(src: &str, records: &[&str], workers: usize) -> Vec<Option<usize>> {
+        let mut route: Route = src.parse().unwrap();
+        records
+            .iter()
+            .map(|record| route.worker(record.as_bytes(), workers))
+            .collect()
+    }
+
+    #[test]
+    fn hash_field() {
+        assert_eq!(
+            route("hash(field(2)) % workers", &["a b", "c d e", "f"], 4),
+            [Some(partition(b"b", 4)), Some(partition(b"d", 4)), None]
+        );
+        assert_eq!(
+            route(r#"hash(field(2, ", "))"#, &["a, b,c"], 4),
+            [Some(partition(b"b,c", 4))]
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        let records = ["a", &"b".repeat(2 << 20), "c", "d"];
+        assert_eq!(
+            route("if len > 1M then worker(0) else rr()", &records, 3),
+            [Some(0), Some(0), Some(1), Some(2)]
+        );
+        assert_eq!(
+            route(
+                "if num(field(1)) >= 10 and num(field(1)) < 20 or len == 0 then 1 else 2",
+                &["15", "25", "", "x"],
+                3
+            ),
+            [Some(1), Some(2), Some(1), None]
+        );
+        assert_eq!(
+            route(
+                "if num(field(1)) > 10 and len > 2 then 1 else 2",
+                &["15", "x"],
+                3
+            ),
+            [Some(2), Some(2)]
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(route("(2 + 3) * 4 - 1", &[""], 100), [Some(19)]);
+        assert_eq!(route("7 - 2 - 1", &[""], 100), [Some(4)]);
+        assert_eq!(
+            route("num(field(0)) / 2", &["9", "nope"], 100),
+            [Some(4), None]
+        );
+        assert_eq!(route("1 / (len - 1)", &["a"], 100), [None]);
+        assert_eq!(route("2k + len", &["abc"], 10_000), [Some(2051)]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = |src: &str| src.parse::<Route>().unwrap_err();
+        assert_eq!(
+            err("if len then 1 else 2"),
+            ParseRouteError::new("expected a comparison", 7)
+        );
+        assert_eq!(err("hash(len)"), ParseRouteError::new("expected field", 5));
+        assert_eq!(
+            err("foo(1)"),
+            ParseRouteError::new("unknown function foo", 0)
+        );
+        assert_eq!(
+            err("1 2"),
+            ParseRouteError::new("unexpected trailing input", 2)
+        );
+        assert_eq!(
+            err("len $"),
+            ParseRouteError::new("unexpected character '$'", 4)
+        );
+        assert_eq!(
+            err(r#"field(1, "x)"#).to_string(),
+            "unterminated string at offset 9"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Routed process pool
+//!
+//! This pool has a fixed number of processes like `Partitioned`, but every record is sent to the
+//! process chosen by evaluating a `Route` against it. To effectively manage cleanup, this needs to
+//! be dropped, so panics while using this may result in zombie processes.
+use super::checkpoint::Routing;
+use super::part::Partitioned;
+use super::pool::{Error, Pool, Spawn};
+use super::route::Route;
+use std::process::Child;
+
+/// A pool that assigns records to processes with a route
+///
+/// The key of every record is the record itself. Processes are spawned lazily the first time a
+/// record is routed to them. Records without a key, or that the route can't be evaluated for, are
+/// sent to processes in a round-robin order.
+#[derive(Debug)]
+pub struct Routed<S> {
+    inner: Partitioned<S>,
+    route: Route,
+}
+
+impl<S: Spawn> Routed<S> {
+    /// Create a new empty pool with `num_procs` processes, that assigns records with `route`
+    ///
+    /// `spawner` is usually a `Command`.
+    ///
+    /// # Panics
+    ///
+    /// If `num_procs` is zero.
+    pub fn new(spawner: S, num_procs: usize, route: Route) -> Self {
+        Routed {
+            inner: Partitioned::new(spawner, num_procs),
+            route,
+        }
+    }
+
+    /// Create a new empty pool that continues routing from a checkpoint
+    ///
+    /// `route` should be the same as the one the checkpoint was saved with. Returns `None` if
+    /// `routing` isn't for a `Routed` pool.
+    pub fn with_routing(spawner: S, mut route: Route, routing: Routing) -> Option<Self> {
+        match routing {
+            Routing::Routed { procs, next, rr } => {
+                let inner =
+                    Partitioned::with_routing(spawner, Routing::Partitioned { procs, next })?;
+                route.set_rr(rr);
+                Some(Routed { inner, route })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<S: Spawn> Pool for Routed<S> {
+    /// Get the next process in round-robin order
+    fn get(&mut self) -> Result<&mut Child, Error> {
+        self.inner.get()
+    }
+
+    /// Get the process that `record` is routed to
+    fn get_key(&mut self, record: &[u8]) -> Result<&mut Child, Error> {
+        match self.route.worker(record, self.inner.num_procs()) {
+            Some(ind) => self.inner.get_index(ind),
+            None => self.inner.get(),
+        }
+    }
+
+    /// The partition of the process most recently returned
+    fn slot(&self) -> usize {
+        self.inner.slot()
+    }
+
+    /// Replace the process most recently returned
+    fn respawn(&mut self) -> Result<&mut Child, Error> {
+        self.inner.respawn()
+    }
+
+    fn routing(&self) -> Option<Routing> {
+        match self.inner.routing()? {
+            Routing::Partitioned { procs, next } => Some(Routing::Routed {
+                procs,
+                next,
+                rr: self.route.rr(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Wait for all processes to finish successfully
+    ///
+    /// Errors will terminate early and not wait for reamining processes to finish. To continue
+    /// waiting for them anyway you can continue to call join until you get a success, this will
+    /// indicate that there are no more running processes under management by the pool.
+    fn join(&mut self) -> Result<(), Error> {
+        self.inner.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Routed;
+    use crate::{Pool, Route, Routing};
+    use std::process::Command;
+
+    #[test]
+    fn restore_routing() {
+        let route: Route = "if len > 1 then rr() else worker(0)".parse().unwrap();
+        let routing = Routing::Routed {
+            procs: 3,
+            next: 2,
+            rr: 1,
+        };
+        let mut pool = Routed::with_routing(Command::new("true"), route.clone(), routing).unwrap();
+        assert_eq!(pool.routing(), Some(routing));
+        pool.get_key(b"ab").unwrap();
+        assert_eq!(pool.slot(), 1);
+        pool.get_key(b"").unwrap();
+        assert_eq!(pool.slot(), 0);
+        pool.join().unwrap();
+        let partitioned = Routing::Partitioned { procs: 3, next: 2 };
+        assert!(Routed::with_routing(Command::new("true"), route, partitioned).is_none());
+    }
+}
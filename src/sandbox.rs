@@ -0,0 +1,189 @@
+//! Sandboxing for child processes
+//!
+//! Restricts what spawned children can do using Landlock for filesystem access, and seccomp and
+//! Landlock for network access. Restrictions are applied in the child right before it execs, so
+//! xstream itself is never restricted. By default children are only spawned if the kernel fully
+//! enforces the Landlock filesystem restrictions, but sandboxes can be made best effort so that
+//! older kernels run children with whatever restrictions they support.
+use landlock::{
+    path_beneath_rules, Access, AccessFs, AccessNet, CompatLevel, Compatible, Ruleset, RulesetAttr,
+    RulesetCreated, RulesetCreatedAttr, RulesetStatus, ABI,
+};
+use seccompiler::{
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule, TargetArch,
+};
+use std::env;
+use std::io::{self, ErrorKind};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The landlock abi to target, newer features are only dropped on older kernels if best effort
+const LANDLOCK_ABI: ABI = ABI::V5;
+
+/// The bit set in the numbers of x32 system calls
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: i64 = 0x4000_0000;
+
+/// A set of restrictions to apply to spawned children
+///
+/// By default nothing is restricted.
+#[derive(Debug, Default, Clone)]
+pub struct Sandbox {
+    deny_network: bool,
+    writable: Option<Vec<PathBuf>>,
+    best_effort: bool,
+}
+
+impl Sandbox {
+    /// Create a new sandbox without any restrictions
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prevent children from creating any sockets except unix domain sockets
+    ///
+    /// Children also can't use `io_uring`, which could create sockets without a system call. On
+    /// kernels with Landlock network rules they can't bind or connect TCP sockets either, even
+    /// ones they inherited, regardless of whether this is best effort.
+    pub fn deny_network(&mut self) -> &mut Self {
+        self.deny_network = true;
+        self
+    }
+
+    /// Make the entire filesystem read only except for files beneath `writable`
+    ///
+    /// Calling this multiple times adds more writable paths.
+    pub fn read_only<P: Into<PathBuf>>(
+        &mut self,
+        writable: impl IntoIterator<Item = P>,
+    ) -> &mut Self {
+        self.writable
+            .get_or_insert_with(Vec::new)
+            .extend(writable.into_iter().map(Into::into));
+        self
+    }
+
+    /// Run children with only the filesystem restrictions the kernel supports
+    ///
+    /// Without this, children fail to spawn unless every filesystem restriction is enforced.
+    pub fn best_effort(&mut self) -> &mut Self {
+        self.best_effort = true;
+        self
+    }
+
+    /// Apply this sandbox to every child spawned from `command`
+    ///
+    /// # Errors
+    ///
+    /// If the landlock ruleset or seccomp filter can't be created, or if the kernel can't fully
+    /// enforce the ruleset and this isn't best effort.
+    pub fn attach(&self, command: &mut Command) -> io::Result<()> {
+        let level = if self.best_effort {
+            CompatLevel::BestEffort
+        } else {
+            CompatLevel::HardRequirement
+        };
+        let mut ruleset = self
+            .writable
+            .as_ref()
+            .map(|writable| landlock_ruleset(writable, level))
+            .transpose()?;
+        let (mut network, filter) = if self.deny_network {
+            (Some(network_ruleset()?), Some(network_filter()?))
+        } else {
+            (None, None)
+        };
+        let best_effort = self.best_effort;
+        // SAFETY: the ruleset and filter are created ahead of time, so the child only needs to
+        // make system calls. The child has its own copy of the ruleset, so it can consume it.
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(ruleset) = ruleset.take() {
+                    let status = ruleset
+                        .restrict_self()
+                        .map_err(|_| io::Error::from(ErrorKind::PermissionDenied))?;
+                    if !best_effort && status.ruleset != RulesetStatus::FullyEnforced {
+                        return Err(io::Error::from(ErrorKind::PermissionDenied));
+                    }
+                }
+                if let Some(network) = network.take() {
+                    network
+                        .restrict_self()
+                        .map_err(|_| io::Error::from(ErrorKind::PermissionDenied))?;
+                }
+                if let Some(filter) = &filter {
+                    seccompiler::apply_filter(filter)
+                        .map_err(|_| io::Error::from(ErrorKind::PermissionDenied))?;
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Create a landlock ruleset that allows reading everything but only writing to `writable`
+///
+/// With a hard requirement, this fails if the kernel doesn't support every restriction.
+fn landlock_ruleset(writable: &Vec<PathBuf>, level: CompatLevel) -> io::Result<RulesetCreated> {
+    let read = AccessFs::from_read(LANDLOCK_ABI);
+    let all = AccessFs::from_all(LANDLOCK_ABI);
+    Ruleset::default()
+        .set_compatibility(level)
+        .handle_access(all)
+        .and_then(Ruleset::create)
+        .and_then(|rules| rules.add_rules(path_beneath_rules(["/"], read)))
+        // writing to /dev/null is always safe and commonly expected
+        .and_then(|rules| rules.add_rules(path_beneath_rules(["/dev/null"], all)))
+        .and_then(|rules| rules.add_rules(path_beneath_rules(writable, all)))
+        .map_err(io::Error::other)
+}
+
+/// Create a landlock ruleset that denies binding or connecting any TCP socket
+///
+/// This is always best effort, since the seccomp filter already denies creating sockets.
+fn network_ruleset() -> io::Result<RulesetCreated> {
+    Ruleset::default()
+        .set_compatibility(CompatLevel::BestEffort)
+        .handle_access(AccessNet::from_all(LANDLOCK_ABI))
+        .and_then(Ruleset::create)
+        .map_err(io::Error::other)
+}
+
+/// Create a seccomp filter that fails any attempt to create a non unix socket or use `io_uring`
+///
+/// Only system calls of the native architecture are allowed, so 32 bit processes, which create
+/// sockets with `socketcall`, are killed.
+fn network_filter() -> io::Result<BpfProgram> {
+    let arch = TargetArch::try_from(env::consts::ARCH).map_err(io::Error::other)?;
+    let not_unix = SeccompCondition::new(
+        0,
+        SeccompCmpArgLen::Dword,
+        SeccompCmpOp::Ne,
+        libc::AF_UNIX as u64,
+    )
+    .and_then(|cond| SeccompRule::new(vec![cond]))
+    .map_err(io::Error::other)?;
+    #[cfg_attr(not(target_arch = "x86_64"), allow(unused_mut))]
+    let mut rules = vec![
+        (libc::SYS_socket, vec![not_unix.clone()]),
+        (libc::SYS_io_uring_setup, Vec::new()),
+    ];
+    // NOTE x32 system calls are native, but numbered differently
+    #[cfg(target_arch = "x86_64")]
+    rules.extend([
+        (libc::SYS_socket | X32_SYSCALL_BIT, vec![not_unix]),
+        (libc::SYS_io_uring_setup | X32_SYSCALL_BIT, Vec::new()),
+    ]);
+    let filter = SeccompFilter::new(
+        rules.into_iter().collect(),
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EACCES as u32),
+        arch,
+    )
+    .map_err(io::Error::other)?;
+    BpfProgram::try_from(filter).map_err(io::Error::other)
+}
@@ -0,0 +1,228 @@
+//! Guessing how unknown input is split into records
+//!
+//! Dumps come in many shapes, and splitting one with the wrong mode usually doesn't fail, it just
+//! sends children garbage. This looks at the start of the input for the structure of each format
+//! xstream can split, from the most to the least specific, and explains the evidence it found.
+use super::detect::detect_delimiter;
+use super::framing::Framing;
+use std::fmt::{self, Display, Formatter};
+use std::str;
+
+/// The number of complete records a sample must have to be recognized, unless it's all of them
+const MIN_RECORDS: usize = 2;
+
+/// A way of splitting input into records
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// JSON lines, a JSON value on every line
+    Jsonl,
+    /// CSV with quoted fields, where delimiters inside quotes don't end records
+    Csv(&'static [u8]),
+    /// Records followed by a delimiter
+    Delimited(&'static [u8]),
+    /// Records prefixed by their length
+    Framed(Framing),
+}
+
+impl Display for Format {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Format::Jsonl => write!(fmt, "jsonl"),
+            Format::Csv(delim) => {
+                write!(fmt, "csv with records ending in {}", delim.escape_ascii())
+            }
+            Format::Delimited(delim) => {
+                write!(fmt, "records delimited by {}", delim.escape_ascii())
+            }
+            Format::Framed(Framing::Netstring) => write!(fmt, "netstrings"),
+            Format::Framed(Framing::U32Le) => write!(fmt, "u32le length prefixed records"),
+            Format::Framed(Framing::Varint) => write!(fmt, "varint length prefixed records"),
+        }
+    }
+}
+
+/// Guess the format of input that starts with `sample`, and why
+///
+/// Length prefixes are only recognized if they frame the records in `sample` exactly. Text is
+/// recognized as JSON lines if every line is an object or array, and as CSV if every line has
+/// the same number of fields and some are quoted. Otherwise the delimiter is guessed like
+/// [`detect_delimiter`]. Binary input can also be varint length prefixed. Returns `None` if
+/// nothing fits.
+#[must_use]
+pub fn sniff_format(sample: &[u8]) -> Option<(Format, String)> {
+    for framing in [Framing::Netstring, Framing::U32Le] {
+        if let Some(found) = framed(sample, framing) {
+            return Some(found);
+        }
+    }
+    if is_text(sample) {
+        if let Some(found) = jsonl(sample).or_else(|| csv(sample)) {
+            return Some(found);
+        }
+        detect_delimiter(sample).map(|delim| {
+            let count = sample.windows(delim.len()).filter(|w| w == &delim).count();
+            (
+                Format::Delimited(delim),
+                format!("the text has {count} of them and no other structure"),
+            )
+        })
+    } else {
+        framed(sample, Framing::Varint)
+    }
+}
+
+/// Whether `sample` looks like text, utf-8 without control characters other than delimiters
+fn is_text(sample: &[u8]) -> bool {
+    let valid = match str::from_utf8(sample) {
+        Ok(_) => true,
+        // the sample can end in the middle of a character
+        Err(err) => err.error_len().is_none(),
+    };
+    valid
+        && sample.iter().all(|&b| {
+            b >= b' '
+                || matches!(
+                    b,
+                    b'\0' | b'\t' | b'\n' | b'\x0c' | b'\r' | b'\x1b' | b'\x1e'
+                )
+        })
+}
+
+/// Check that `sample` is a sequence of records framed with `framing`
+fn framed(sample: &[u8], framing: Framing) -> Option<(Format, String)> {
+    let mut rest = sample;
+    let mut records = 0;
+    let mut buf = Vec::new();
+    while !rest.is_empty() {
+        buf.clear();
+        match framing.read_frame(&mut rest, &mut buf) {
+            Ok(true) => records += 1,
+            // the sample can end in the middle of a record
+            _ if records >= MIN_RECORDS => break,
+            _ => return None,
+        }
+    }
+    (records > 0).then(|| {
+        (
+            Format::Framed(framing),
+            format!("the first {records} records are framed exactly"),
+        )
+    })
+}
+
+/// The complete lines of `sample`, without the last if it could be cut off
+fn lines(sample: &[u8]) -> Option<Vec<&[u8]>> {
+    let end = sample.iter().rposition(|&b| b == b'\n')?;
+    Some(sample[..end].split(|&b| b == b'\n').collect())
+}
+
+/// Check that every line of `sample` is a JSON object or array
+fn jsonl(sample: &[u8]) -> Option<(Format, String)> {
+    let lines = lines(sample)?;
+    let values = lines
+        .iter()
+        .map(|line| line.trim_ascii())
+        .filter(|line| !line.is_empty())
+        .try_fold(0, |values, line| {
+            matches!(
+                (line.first(), line.last()),
+                (Some(b'{'), Some(b'}')) | (Some(b'['), Some(b']'))
+            )
+            .then_some(values + 1)
+        })?;
+    (values > 0).then(|| {
+        (
+            Format::Jsonl,
+            format!("all {values} lines are JSON objects or arrays"),
+        )
+    })
+}
+
+/// Check that every record of `sample` has the same number of fields, and some are quoted
+fn csv(sample: &[u8]) -> Option<(Format, String)> {
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut breaks = 0;
+    let mut crlf = true;
+    let mut commas = 0;
+    let mut fields = None;
+    let mut records = 0;
+    for (ind, &byte) in sample.iter().enumerate() {
+        match byte {
+            b'"' => {
+                quoted = true;
+                in_quotes = !in_quotes;
+            }
+            b'\n' if in_quotes => breaks += 1,
+            b',' if !in_quotes => commas += 1,
+            b'\n' => {
+                if *fields.get_or_insert(commas + 1) != commas + 1 {
+                    return None;
+                }
+                crlf &= ind > 0 && sample[ind - 1] == b'\r';
+                records += 1;
+                commas = 0;
+            }
+            _ => {}
+        }
+    }
+    let fields = fields?;
+    if !quoted || fields < 2 || records < MIN_RECORDS {
+        return None;
+    }
+    let delim: &[u8] = if crlf { b"\r\n" } else { b"\n" };
+    Some((
+        Format::Csv(delim),
+        format!(
+            "all {records} records have {fields} fields, some are quoted, and {breaks} quoted \
+             fields span lines"
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sniff_format, Format};
+    use crate::Framing;
+
+    fn sniff(sample: &[u8]) -> Option<Format> {
+        sniff_format(sample).map(|(format, _)| format)
+    }
+
+    #[test]
+    fn framed() {
+        assert_eq!(
+            sniff(b"3:abc,2:de,"),
+            Some(Format::Framed(Framing::Netstring))
+        );
+        assert_eq!(
+            sniff(b"\x03\0\0\0abc\x02\0\0\0de\x05\0\0\0fg"),
+            Some(Format::Framed(Framing::U32Le))
+        );
+        assert_eq!(
+            sniff(b"\x03\x80\x81\x82\x02\xff\xfe"),
+            Some(Format::Framed(Framing::Varint))
+        );
+    }
+
+    #[test]
+    fn jsonl() {
+        assert_eq!(sniff(b"{\"a\": 1}\n[2, 3]\n\n{\"b\""), Some(Format::Jsonl));
+        assert_eq!(sniff(b"{\"a\": 1}\nnope\n"), Some(Format::Delimited(b"\n")));
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(sniff(b"a,b\n\"c\nd\",e\nf,g\nh"), Some(Format::Csv(b"\n")));
+        assert_eq!(sniff(b"a,\"b\"\r\nc,d\r\n"), Some(Format::Csv(b"\r\n")));
+        assert_eq!(sniff(b"a,b\nc,d\n"), Some(Format::Delimited(b"\n")));
+        assert_eq!(sniff(b"a,\"b\"\nc\n"), Some(Format::Delimited(b"\n")));
+    }
+
+    #[test]
+    fn delimited() {
+        assert_eq!(sniff(b"a\0b\0c"), Some(Format::Delimited(b"\0")));
+        assert_eq!(sniff(b"abc"), None);
+    }
+}
@@ -0,0 +1,251 @@
+//! Spilling input to disk when children fall behind
+//!
+//! Producers like network sockets or message queue consumers can time out if they aren't read
+//! promptly, but children with bursty latency can stop accepting records for a while. A spool
+//! reads input on its own thread into a file, so input keeps being read while every child is
+//! busy, and records are split from the file as children catch up. The file is used as a ring
+//! buffer, so it never grows past its size cap, and reading input only waits when it's full.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Read};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+/// The most that's read from the input or the spool at once
+const CHUNK: usize = 64 * 1024;
+
+/// The number of spools created by this process, to give each a unique file
+static SPOOLS: AtomicUsize = AtomicUsize::new(0);
+
+/// The progress of the input through the spool
+#[derive(Debug, Default)]
+struct State {
+    /// The total bytes read from the spool
+    read: u64,
+    /// The total bytes written to the spool
+    written: u64,
+    /// Whether the input has ended
+    done: bool,
+    /// Whether the spool was dropped, so nothing is reading it anymore
+    closed: bool,
+    /// The error that ended the input, if any
+    error: Option<io::Error>,
+}
+
+/// What's shared between the spool and the thread filling it
+#[derive(Debug)]
+struct Shared {
+    file: File,
+    max_size: u64,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    /// Lock the state
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Lock the state and wait until `ready` is true
+    fn wait(&self, ready: impl Fn(&State) -> bool) -> MutexGuard<'_, State> {
+        let state = self.lock();
+        self.changed
+            .wait_while(state, |state| !ready(state))
+            .unwrap()
+    }
+
+    /// Update the state and wake anything waiting for it
+    fn update(&self, update: impl FnOnce(&mut State)) {
+        update(&mut self.lock());
+        self.changed.notify_all();
+    }
+
+    /// Copy `input` into the spool until it ends or the spool is dropped
+    fn fill(&self, mut input: impl Read) -> io::Result<()> {
+        let mut chunk = vec![0; CHUNK];
+        loop {
+            let mut data = match input.read(&mut chunk) {
+                Ok(0) => return Ok(()),
+                Ok(len) => &chunk[..len],
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            while !data.is_empty() {
+                let (written, free) = {
+                    let state = self
+                        .wait(|state| state.closed || state.written - state.read < self.max_size);
+                    if state.closed {
+                        return Ok(());
+                    }
+                    (state.written, self.max_size - (state.written - state.read))
+                };
+                // NOTE the unread data is never overwritten, even when this wraps around
+                let at = written % self.max_size;
+                let len = usize::try_from(free.min(self.max_size - at))
+                    .map_or(data.len(), |len| len.min(data.len()));
+                self.file.write_all_at(&data[..len], at)?;
+                self.update(|state| state.written += len as u64);
+                data = &data[len..];
+            }
+        }
+    }
+}
+
+/// Input read ahead into a file of at most a fixed size
+///
+/// This reads like the input it was created from. The file is removed as soon as it's created,
+/// so it's cleaned up however this process exits.
+#[derive(Debug)]
+pub struct Spool {
+    shared: Arc<Shared>,
+    buf: Vec<u8>,
+    pos: usize,
+    filled: usize,
+}
+
+impl Spool {
+    /// Start reading `input` into a spool in `dir` that holds at most `max_size` unread bytes
+    ///
+    /// Errors reading `input` are returned after everything read before them.
+    ///
+    /// # Errors
+    ///
+    /// If the file for the spool can't be created in `dir`.
+    ///
+    /// # Panics
+    ///
+    /// If `max_size` is zero.
+    pub fn new(input: impl Read + Send + 'static, dir: &Path, max_size: u64) -> io::Result<Self> {
+        assert_ne!(max_size, 0, "spools need room for at least one byte");
+        let path = dir.join(format!(
+            ".xstream-spool-{}-{}",
+            process::id(),
+            SPOOLS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        fs::remove_file(&path)?;
+        let shared = Arc::new(Shared {
+            file,
+            max_size,
+            state: Mutex::default(),
+            changed: Condvar::new(),
+        });
+        let filling = Arc::clone(&shared);
+        // NOTE this isn't joined, since it can be blocked reading input that never ends
+        thread::spawn(move || {
+            let res = filling.fill(input);
+            filling.update(|state| {
+                state.done = true;
+                state.error = res.err();
+            });
+        });
+        Ok(Spool {
+            shared,
+            buf: vec![0; CHUNK],
+            pos: 0,
+            filled: 0,
+        })
+    }
+
+    /// The number of bytes read from the input that haven't been read from the spool yet
+    #[must_use]
+    pub fn spooled(&self) -> u64 {
+        let state = self.shared.lock();
+        state.written - state.read
+    }
+}
+
+impl Read for Spool {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let chunk = self.fill_buf()?;
+        let len = chunk.len().min(out.len());
+        out[..len].copy_from_slice(&chunk[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for Spool {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.filled {
+            let shared = &self.shared;
+            let (read, unread) = {
+                let mut state = shared.wait(|state| state.read < state.written || state.done);
+                if state.read == state.written {
+                    return match state.error.take() {
+                        Some(err) => Err(err),
+                        None => Ok(&[]),
+                    };
+                }
+                (state.read, state.written - state.read)
+            };
+            let at = read % shared.max_size;
+            let len = usize::try_from(unread.min(shared.max_size - at))
+                .map_or(self.buf.len(), |len| len.min(self.buf.len()));
+            shared.file.read_exact_at(&mut self.buf[..len], at)?;
+            shared.update(|state| state.read += len as u64);
+            self.pos = 0;
+            self.filled = len;
+        }
+        Ok(&self.buf[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.filled);
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        self.shared.update(|state| state.closed = true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Spool;
+    use std::env;
+    use std::io::{self, Read};
+
+    /// A reader that fails after some input
+    struct Failing(&'static [u8]);
+
+    impl Read for Failing {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(out)? {
+                0 => Err(io::Error::other("input failed")),
+                len => Ok(len),
+            }
+        }
+    }
+
+    #[test]
+    fn wraps_around() {
+        let input: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+        let mut spool = Spool::new(io::Cursor::new(input.clone()), &env::temp_dir(), 7).unwrap();
+        let mut output = Vec::new();
+        spool.read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+        assert_eq!(spool.spooled(), 0);
+    }
+
+    #[test]
+    fn errors_after_input() {
+        let mut spool = Spool::new(Failing(b"abc"), &env::temp_dir(), 1024).unwrap();
+        let mut output = [0; 3];
+        spool.read_exact(&mut output).unwrap();
+        assert_eq!(&output, b"abc");
+        assert_eq!(
+            spool.read(&mut output).unwrap_err().to_string(),
+            "input failed"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Summary statistics of split records
+//!
+//! Records of very different sizes take very different amounts of time to process, which is
+//! usually what causes some children to be much busier than others. This keeps an exact histogram
+//! of record sizes, which only grows with the number of distinct sizes.
+use std::collections::BTreeMap;
+
+/// Counts of the records that were split, and a histogram of their sizes
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    records: u64,
+    bytes: u64,
+    sizes: BTreeMap<usize, u64>,
+}
+
+impl Stats {
+    /// Create new empty statistics
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a record of `size` bytes
+    pub fn record(&mut self, size: usize) {
+        self.records += 1;
+        self.bytes += size as u64;
+        *self.sizes.entry(size).or_default() += 1;
+    }
+
+    /// The number of records counted
+    #[must_use]
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// The total size of every record counted
+    #[must_use]
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// The size that `percent` of records are at most
+    ///
+    /// This uses the nearest rank, so it's always the size of a record that was counted. Returns
+    /// `None` if nothing was counted.
+    ///
+    /// # Panics
+    ///
+    /// If `percent` is more than 100.
+    #[must_use]
+    pub fn percentile(&self, percent: u8) -> Option<usize> {
+        assert!(percent <= 100, "percentile out of range: {percent}");
+        let rank = (self.records * u64::from(percent)).div_ceil(100).max(1);
+        let mut seen = 0;
+        self.sizes.iter().find_map(|(&size, &count)| {
+            seen += count;
+            (seen >= rank).then_some(size)
+        })
+    }
+
+    /// The size of the largest record counted
+    #[must_use]
+    pub fn max(&self) -> Option<usize> {
+        self.sizes.keys().next_back().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stats;
+
+    #[test]
+    fn percentiles() {
+        let mut stats = Stats::new();
+        for size in 1..=100 {
+            stats.record(size);
+        }
+        stats.record(1000);
+        assert_eq!(stats.records(), 101);
+        assert_eq!(stats.bytes(), 6050);
+        assert_eq!(stats.percentile(0), Some(1));
+        assert_eq!(stats.percentile(50), Some(51));
+        assert_eq!(stats.percentile(99), Some(100));
+        assert_eq!(stats.percentile(100), Some(1000));
+        assert_eq!(stats.max(), Some(1000));
+    }
+
+    #[test]
+    fn empty() {
+        let stats = Stats::new();
+        assert_eq!(stats.percentile(50), None);
+        assert_eq!(stats.max(), None);
+    }
+}
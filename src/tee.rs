@@ -0,0 +1,76 @@
+//! Copying input while it's split
+//!
+//! Splitting an ephemeral stream leaves nothing behind to rerun it with. This copies the input to
+//! another writer as it's consumed, which avoids the extra pipe of a separate `tee` process.
+use std::io::{self, BufRead, Read, Write};
+
+/// A reader that writes a copy of everything consumed from it
+///
+/// Only bytes that are consumed are copied, so the copy is exactly the input that was split.
+/// Errors writing the copy are returned by the next read.
+#[derive(Debug)]
+pub struct Tee<R, W> {
+    inner: R,
+    out: W,
+    err: Option<io::Error>,
+}
+
+impl<R: BufRead, W: Write> Tee<R, W> {
+    /// Create a new reader that copies what's consumed from `inner` to `out`
+    pub fn new(inner: R, out: W) -> Self {
+        Tee {
+            inner,
+            out,
+            err: None,
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let buf = self.fill_buf()?;
+        let len = buf.len().min(out.len());
+        out[..len].copy_from_slice(&buf[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead, W: Write> BufRead for Tee<R, W> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if let Some(err) = self.err.take() {
+            return Err(err);
+        }
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if amt > 0 && self.err.is_none() {
+            // NOTE filling a buffer that isn't empty returns the same bytes without reading
+            let res = match self.inner.fill_buf() {
+                Ok(buf) => self.out.write_all(&buf[..amt]),
+                Err(err) => Err(err),
+            };
+            self.err = res.err();
+        }
+        self.inner.consume(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tee;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn copies_consumed() {
+        let input = b"first\nsecond\nthird";
+        let mut copy = Vec::new();
+        let mut tee = Tee::new(BufReader::with_capacity(4, &input[..]), &mut copy);
+        let mut line = Vec::new();
+        tee.read_until(b'\n', &mut line).unwrap();
+        tee.read_until(b'\n', &mut line).unwrap();
+        drop(tee);
+        assert_eq!(copy, b"first\nsecond\n");
+    }
+}
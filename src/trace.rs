@@ -0,0 +1,82 @@
+//! OpenTelemetry spans for child processes
+//!
+//! xstream usually sits in the middle of a larger pipeline, and without spans for the work it does
+//! traces of that pipeline have a gap. This records a span for the lifetime of every child, from
+//! when it's spawned to when it's waited on, with how it exited.
+use super::pool::{Error, Spawn};
+use opentelemetry::trace::{Span, Status, Tracer};
+use opentelemetry::KeyValue;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::process::{Child, Command};
+
+/// A spawner that records a span for the lifetime of every child
+///
+/// Spans are named `worker`, and have the pid of the child, its slot in the pool like in manifests
+/// and audits, and its exit code once it's waited on. Children that fail have an error status.
+pub struct Traced<S, T: Tracer> {
+    inner: S,
+    tracer: T,
+    spans: HashMap<u32, T::Span>,
+}
+
+impl<S: Spawn, T: Tracer> Traced<S, T> {
+    /// Wrap `inner` so that children it spawns have spans created by `tracer`
+    pub fn new(inner: S, tracer: T) -> Self {
+        Traced {
+            inner,
+            tracer,
+            spans: HashMap::new(),
+        }
+    }
+}
+
+impl<S, T: Tracer> Debug for Traced<S, T> {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        fmt.debug_struct("Traced").finish_non_exhaustive()
+    }
+}
+
+impl<S: Spawn, T: Tracer> Spawn for Traced<S, T> {
+    fn command(&mut self) -> &mut Command {
+        self.inner.command()
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<Child, Error> {
+        let mut span = self.tracer.start("worker");
+        let res = self.inner.spawn(slot);
+        match &res {
+            Ok(child) => {
+                span.set_attributes([
+                    KeyValue::new("process.pid", i64::from(child.id())),
+                    KeyValue::new("xstream.worker", i64::try_from(slot).unwrap_or(i64::MAX)),
+                ]);
+                self.spans.insert(child.id(), span);
+            }
+            Err(err) => {
+                span.set_status(Status::error(err.to_string()));
+                span.end();
+            }
+        }
+        res
+    }
+
+    fn wait(&mut self, child: Child) -> Result<(), Error> {
+        let span = self.spans.remove(&child.id());
+        let res = self.inner.wait(child);
+        if let Some(mut span) = span {
+            match &res {
+                Ok(()) => span.set_attribute(KeyValue::new("process.exit.code", 0)),
+                Err(Error::NonZeroExitCode(code)) => {
+                    span.set_attribute(KeyValue::new("process.exit.code", i64::from(*code)));
+                }
+                Err(_) => {}
+            }
+            if let Err(err) = &res {
+                span.set_status(Status::error(err.to_string()));
+            }
+            span.end();
+        }
+        res
+    }
+}
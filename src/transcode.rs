@@ -0,0 +1,110 @@
+//! Character encoding conversion for input streams
+//!
+//! Delimiters are matched against raw bytes, so input in an encoding like UTF-16 needs to be
+//! converted before it can be split. `Transcoder` does that conversion while reading, which avoids
+//! the overhead of piping the input through a separate process first.
+use encoding_rs::{CoderResult, Decoder, Encoding};
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, BufRead, Read};
+
+/// The size of the buffer for converted output
+const BUF_SIZE: usize = 8192;
+
+/// A reader that converts input from another character encoding to UTF-8
+///
+/// Malformed input is replaced with the unicode replacement character.
+pub struct Transcoder<R> {
+    inner: R,
+    decoder: Decoder,
+    buf: Box<[u8]>,
+    pos: usize,
+    len: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Transcoder<R> {
+    /// Create a new reader that converts `inner` from `encoding` to UTF-8
+    ///
+    /// A byte order mark for `encoding` at the start of the input is removed.
+    pub fn new(inner: R, encoding: &'static Encoding) -> Self {
+        Transcoder {
+            inner,
+            decoder: encoding.new_decoder_with_bom_removal(),
+            buf: vec![0; BUF_SIZE].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R> Debug for Transcoder<R> {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        fmt.debug_struct("Transcoder")
+            .field("encoding", &self.decoder.encoding())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R: BufRead> Read for Transcoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let buf = self.fill_buf()?;
+        let len = buf.len().min(out.len());
+        out[..len].copy_from_slice(&buf[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for Transcoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.len && !self.done {
+            let src = self.inner.fill_buf()?;
+            let last = src.is_empty();
+            let (res, read, written, _) = self.decoder.decode_to_utf8(src, &mut self.buf, last);
+            self.inner.consume(read);
+            self.pos = 0;
+            self.len = written;
+            self.done = last && res == CoderResult::InputEmpty;
+        }
+        Ok(&self.buf[self.pos..self.len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Transcoder;
+    use encoding_rs::{UTF_16LE, WINDOWS_1252};
+    use std::io::{BufReader, Read};
+
+    fn transcode(input: &[u8], chunk: usize, encoding: &'static encoding_rs::Encoding) -> String {
+        let mut res = String::new();
+        Transcoder::new(BufReader::with_capacity(chunk, input), encoding)
+            .read_to_string(&mut res)
+            .unwrap();
+        res
+    }
+
+    #[test]
+    fn latin1() {
+        assert_eq!(transcode(b"caf\xe9\n", 2, WINDOWS_1252), "café\n");
+    }
+
+    #[test]
+    fn utf16_split_across_reads() {
+        let input: Vec<u8> = "\u{feff}a\nβ\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert_eq!(transcode(&input, 1, UTF_16LE), "a\nβ\n");
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(transcode(b"a\x00\x00\xd8", 3, UTF_16LE), "a\u{fffd}");
+    }
+}